@@ -0,0 +1,2560 @@
+// Creating and extracting sharded archives. Every worker thread owns one
+// shard (`{archive_name}.{idx}.tar`, possibly split into parts); the calling
+// thread enumerates the target and hands the work items out.
+
+// Multi-threading
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{
+    Sender, Receiver, channel, RecvTimeoutError, TryRecvError
+};
+use std::thread::JoinHandle;
+use std::{thread, time::Duration};
+
+// Tar files
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{
+    File, canonicalize, create_dir_all, metadata, symlink_metadata, read,
+    read_link, write
+};
+use std::io::{self, Read, Write};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tar::{Builder, EntryType, Header, HeaderMode};
+use walkdir::WalkDir;
+
+use crate::codec::Codec;
+use crate::dedup::{Reference, References, dedup_path, find_duplicates};
+use crate::distribution::Distribution;
+use crate::error::{ArchiverError, ErrorGroup, group_errors};
+use crate::exclude::{ExcludeSet, IgnoreFiles, VCS_EXCLUDES};
+use crate::format::{Format, NAME_FIELD, split_ustar};
+use crate::extract::{
+    ConflictPolicy, ExtractConfig, ExtractReport, Preview, copy_member,
+    extract_shard, find_member, link_deferred, preview_shard, process_umask,
+    strip_path, test_shard
+};
+use crate::interrupt::interrupted;
+use crate::journal::Journal;
+use crate::manifest::{Fragment, fragment_path};
+use crate::plan::Plan;
+use crate::progress::{Progress, ProgressFn, Tracker};
+use crate::sha256::Sha256;
+use crate::size::{format_count, format_size};
+use crate::sparse;
+use crate::transform::EntryTransform;
+use crate::xattrs::{self, append_pax};
+use crate::shard::{
+    CountingWriter, NameTemplate, PartChecksum, STDOUT, ShardConfig,
+    ShardLimits, ShardOutput, ShardWriter
+};
+
+
+// Status messages go to stdout, unless the archive itself is streamed there
+macro_rules! status {
+    ($archive_name:expr, $($arg:tt)*) => {
+        if $archive_name == STDOUT {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+
+// What one worker archived into its shard
+pub struct ShardMetrics {
+    pub shard: u32,
+    pub files: u64,
+    // Bytes written to the shard's files
+    pub bytes: u64,
+    // Time spent archiving items (excluding waiting for work)
+    pub active: Duration
+}
+
+
+impl fmt::Display for ShardMetrics {
+    fn fmt(& self, f: & mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "shard {}: {} files, {}, {}s",
+            self.shard, format_count(self.files), format_size(self.bytes),
+            self.active.as_secs()
+        )
+    }
+}
+
+
+// Paths, each with why it could not be read
+pub type PathErrors = Vec<(PathBuf, ArchiverError)>;
+
+
+// What create would archive (see Archiver::enumerate)
+pub struct WorkList {
+    pub items: Vec<PathBuf>,
+    // Paths the walk could not read, see CreateReport::unreadable
+    pub unreadable: PathErrors,
+    // Regular files left out by the size limits, and their total size
+    pub excluded_files: u64,
+    pub excluded_bytes: u64
+}
+
+
+// Outcome of archiving a set of work items
+pub struct CreateReport {
+    // Items the workers reported as archived
+    pub processed: Vec<PathBuf>,
+    // Items that could not be archived (e.g. unreadable files). Only
+    // returned with `ignore_errors`, otherwise create fails with
+    // ArchiverError::Failed.
+    pub failed: PathErrors,
+    // Special files that were left out: sockets, and FIFOs and device nodes
+    // with `skip_special`
+    pub skipped: Vec<PathBuf>,
+    // Items the entry transform left out
+    pub dropped: Vec<PathBuf>,
+    // Regular files left out for being larger than `max_file_size` when the
+    // worker came to archive them
+    pub oversized: Vec<PathBuf>,
+    // Paths the walk of the target could not read (e.g. unreadable
+    // directories), which are not in the archive. Empty with `fail_fast`,
+    // which fails create instead.
+    pub unreadable: PathErrors,
+    // SHA-256 of every shard file written (only if requested)
+    pub checksums: Vec<PartChecksum>,
+    // One entry per shard, in shard order
+    pub shards: Vec<ShardMetrics>,
+    // The run was stopped by Ctrl-C: the shards are valid, but only hold
+    // `processed`
+    pub interrupted: bool,
+    // Wall time of the whole create, enumeration included
+    pub elapsed: Duration
+}
+
+
+// How evenly the files were spread over the worker shards, to tell whether
+// more threads actually helped
+pub struct Utilization {
+    pub shards: usize,
+    // Shards that received at least one file
+    pub busy: usize,
+    pub min_files: u64,
+    pub max_files: u64
+}
+
+
+impl CreateReport {
+    // The failed and unreadable items, grouped by what went wrong
+    pub fn error_groups(& self) -> Vec<ErrorGroup> {
+        group_errors(self.failed.iter().chain(& self.unreadable))
+    }
+
+    // Bytes written to the shards in this run
+    pub fn bytes(& self) -> u64 {
+        self.shards.iter().map(|shard| shard.bytes).sum()
+    }
+
+    pub fn utilization(& self) -> Utilization {
+        let files = self.shards.iter().map(|shard| shard.files);
+        Utilization {
+            shards: self.shards.len(),
+            busy: self.shards.iter().filter(|shard| shard.files > 0).count(),
+            min_files: files.clone().min().unwrap_or(0),
+            max_files: files.max().unwrap_or(0)
+        }
+    }
+}
+
+
+impl fmt::Display for Utilization {
+    fn fmt(& self, f: & mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{} of {} worker shards received files ({} to {} files each)",
+            self.busy, self.shards, format_count(self.min_files),
+            format_count(self.max_files)
+        )
+    }
+}
+
+
+// How patient the create workers and the collecting main thread are. An
+// idle worker polls its queue `max_tries` times, `retry_wait` apart, before
+// giving up; the main thread fails if no worker reports anything for
+// `collect_timeout`. Raise these on slow (e.g. network) storage, where a
+// single file can take longer than the defaults allow.
+#[derive(Clone, Copy, Debug)]
+pub struct Timing {
+    pub max_tries: u32,
+    pub retry_wait: Duration,
+    pub collect_timeout: Duration
+}
+
+
+impl Default for Timing {
+    fn default() -> Timing {
+        Timing {
+            max_tries: 100,
+            retry_wait: Duration::from_millis(128),
+            collect_timeout: Duration::from_millis(4000)
+        }
+    }
+}
+
+
+// Sent by a worker for every item it took from its queue
+struct Archived {
+    path: PathBuf,
+    shard: u32,
+    // Bytes the item added to the shard
+    bytes: u64,
+    // Why the item could not be archived
+    error: Option<ArchiverError>,
+    // The item is a special file that was left out
+    skipped: bool,
+    // The entry transform left the item out
+    dropped: bool,
+    // The item is larger than `max_file_size`
+    oversized: bool
+}
+
+
+#[cfg(unix)]
+fn device_id(meta: & std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.dev())
+}
+
+
+#[cfg(not(unix))]
+fn device_id(_: & std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+
+// Bytes available to an unprivileged writer on the file system of `dir`
+#[cfg(unix)]
+fn free_space(dir: & Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(io::Error::other)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), & mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+
+#[cfg(not(unix))]
+fn free_space(_: & Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space can only be checked on Unix"
+    ))
+}
+
+
+// What archiving `work_items` adds to the shards, roughly: a header block
+// per item and the file data padded to whole blocks (long names, PAX
+// records and end-of-archive markers come on top)
+fn estimated_size(work_items: & [PathBuf]) -> u64 {
+    work_items.iter().map(|item| {
+        let data = match symlink_metadata(item) {
+            Ok(meta) if meta.is_file() => meta.len().div_ceil(512) * 512,
+            _ => 0
+        };
+        512 + data
+    }).sum()
+}
+
+
+// The nearest existing directory at or above `path`'s parent, where the
+// file will be created
+fn existing_dir(path: & Path) -> PathBuf {
+    let mut dir = path.parent().unwrap_or(Path::new(""));
+    while ! dir.as_os_str().is_empty() && ! dir.is_dir() {
+        dir = dir.parent().unwrap_or(Path::new(""));
+    }
+    if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir.to_path_buf()
+    }
+}
+
+
+// Whether a walk error is a path removed after its directory was listed:
+// on a live file system that is expected, and not a failure
+fn vanished(error: & walkdir::Error) -> bool {
+    error.io_error().is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+
+// With `one_file_system`, directories on a different device than
+// `folder_path` (mount points) are listed but not descended into, like
+// `tar --one-file-system`.
+// Paths that cannot be read while walking are left out and added to
+// `unreadable`; without it, the first one fails the walk. Paths that
+// disappear during the walk are left out either way.
+// `follow_root` follows `folder_path` itself if it is a symlink, but no link
+// below it.
+fn find_files(
+        folder_path: & str, follow_links: bool, follow_root: bool,
+        excludes: & ExcludeSet, one_file_system: bool,
+        mut unreadable: Option<& mut PathErrors>
+    ) -> Result<Vec<PathBuf>, ArchiverError> {
+
+    let root = Path::new(folder_path);
+    // A single file (or link) is the whole work list: there is nothing to
+    // walk, exclude or look for ignore files in
+    let root_meta = if follow_links || follow_root {
+        metadata(root)?
+    } else {
+        symlink_metadata(root)?
+    };
+    if ! root_meta.is_dir() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let root_dev = if one_file_system {
+        device_id(& metadata(root)?)
+    } else {
+        None
+    };
+    let mut ignore_files = IgnoreFiles::new(root);
+    let mut walker = WalkDir::new(folder_path).follow_links(follow_links)
+        .into_iter()
+        // Excluded directories are pruned here, so their contents are never
+        // visited
+        .filter_entry(|e| {
+            if e.depth() == 0 {
+                return true;
+            }
+            let is_dir = e.file_type().is_dir();
+            let rel = e.path().strip_prefix(root).unwrap_or(e.path());
+            let excluded = ! excludes.is_empty()
+                && excludes.is_excluded(& rel.to_string_lossy(), is_dir);
+            ! excluded && ! ignore_files.is_ignored(e.path(), is_dir)
+        });
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    while let Some(entry) = walker.next() {
+        let entry = match (entry, unreadable.as_deref_mut()) {
+            (Ok(entry), _) => entry,
+            (Err(error), _) if vanished(& error) => {
+                let path = error.path().unwrap_or(root);
+                eprintln!(
+                    "Skipping {}: it disappeared during the walk",
+                    path.display()
+                );
+                continue;
+            }
+            (Err(error), Some(unreadable)) => {
+                let path = error.path().unwrap_or(root);
+                unreadable.push((path.to_path_buf(), error.into()));
+                continue;
+            }
+            (Err(error), None) => return Err(error.into())
+        };
+        let path = entry.path();
+
+        let other_device = root_dev.is_some() && entry.depth() > 0
+            && entry.file_type().is_dir()
+            && match entry.metadata() {
+                Ok(meta) => device_id(& meta) != root_dev,
+                // Gone: it is left out when the walk tries to list it
+                Err(error) if vanished(& error) => false,
+                Err(error) => return Err(error.into())
+            };
+        if other_device {
+            eprintln!(
+                "Not descending into {}: it is on a different file system",
+                path.display()
+            );
+            walker.skip_current_dir();
+        }
+        // Not necessarily UTF-8
+        files.push(path.to_path_buf());
+    }
+
+    Ok(files)
+}
+
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: & [u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: & [u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).to_string())
+}
+
+
+// The items that are further names of a file an earlier item names (the same
+// device and inode), each with that earlier item
+#[cfg(unix)]
+fn find_hard_links(work_items: & [PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+    let mut first: HashMap<(u64, u64), & PathBuf> = HashMap::new();
+    let mut links = HashMap::new();
+    for item in work_items {
+        let meta = match symlink_metadata(item) {
+            Ok(meta) if meta.is_file() && meta.nlink() > 1 => meta,
+            _ => continue
+        };
+        let file = (meta.dev(), meta.ino());
+        match first.get(& file) {
+            Some(original) => {
+                links.insert(item.clone(), (* original).clone());
+            }
+            None => {
+                first.insert(file, item);
+            }
+        }
+    }
+    links
+}
+
+
+#[cfg(not(unix))]
+fn find_hard_links(_: & [PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    HashMap::new()
+}
+
+
+// Newline-separated paths from `source` (a file, or "-" for stdin), for
+// when an external tool selects what to archive. Blank lines are skipped, as
+// is a trailing "\r" (lists written on Windows). On Unix the paths need not
+// be UTF-8. A compressed list is refused, since this build cannot decompress
+// it (see Codec).
+fn read_file_list(source: & str) -> Result<Vec<PathBuf>, ArchiverError> {
+    let text = if source == "-" {
+        let mut text = Vec::new();
+        io::stdin().read_to_end(& mut text)?;
+        text
+    } else {
+        read(source)?
+    };
+    match Codec::from_magic(& text) {
+        // "BZh" may just as well start a file name
+        Codec::Plain | Codec::Bzip2 => {}
+        codec => return Err(ArchiverError::Compressed {
+            path: source.to_string(), codec
+        })
+    }
+    Ok(text.split(|& b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| ! line.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+
+fn set_mutex<T: Copy>(mutex: & Arc<Mutex<T>>, val: T) {
+    let mut lock = mutex.lock().unwrap();
+    * lock = val;
+    drop(lock);
+}
+
+
+fn get_mutex<T: Copy>(mutex: & Arc<Mutex<T>>) -> T {
+    let lock = mutex.lock().unwrap();
+    let val = * lock;
+    drop(lock);
+    val
+}
+
+
+fn take_mutex_try_many<T>(
+        rx: & Arc<Mutex<Receiver<T>>>,
+        max_try: u32, wait: Duration,
+        completed: & Arc<Mutex<bool>>
+    ) -> Result<T, TryRecvError> {
+
+    let mut ct = 0;
+    loop {
+        // Grab lock the the guard mutex, and take data from channel
+        let data = rx.lock().unwrap();
+        let datum = data.try_recv();
+        drop(data);
+        match datum {
+            Ok(input) => {
+                return Ok(input);
+            }
+            Err(error) => {
+                if (ct > max_try) || get_mutex(completed) || interrupted() {
+                    return Err(error);
+                }
+                ct += 1;
+                thread::sleep(wait);
+            }
+        }
+    }
+}
+
+
+// Collect `ct_expect` results, failing if none arrives for `wait`. After an
+// interrupt, collection continues until every sender (worker) has exited, so
+// the result holds everything that made it into a shard. `observe` is called
+// with every result, and with None whenever no result arrived for a while.
+fn collect_expected<T>(
+        ct_expect: usize, rx: Receiver<T>, wait: Duration,
+        mut observe: impl FnMut(Option<& T>)
+    ) -> Result<Vec<T>, ArchiverError> {
+    let mut items: Vec<T> = Vec::new();
+    // Non-blocking (but patient) data collection, in short slices so that
+    // an interrupt is noticed promptly
+    let slice = Duration::from_millis(128);
+    let mut idle = Duration::ZERO;
+    let mut ct_recv = 0;
+    loop {
+        if ct_recv >= ct_expect {
+            break;
+        }
+        match rx.recv_timeout(slice) {
+            Ok(result) => {
+                observe(Some(& result));
+                items.push(result);
+                ct_recv +=1 ;
+                idle = Duration::ZERO;
+            }
+            Err(RecvTimeoutError::Disconnected) if interrupted() => break,
+            Err(RecvTimeoutError::Timeout) if interrupted() || idle < wait => {
+                observe(None);
+                idle += slice;
+            }
+            Err(error) => {
+                return Err(ArchiverError::Worker(format!(
+                    "{} while collecting {} out of {}",
+                    error, ct_recv, ct_expect
+                )));
+            }
+        }
+    }
+    Ok(items)
+}
+
+
+fn is_symlink(path: & Path) -> bool {
+    path.symlink_metadata().map(
+        |metadata| metadata.file_type().is_symlink()
+    ).unwrap_or(false)
+}
+
+
+// FIFOs, sockets and device nodes, which `append_path` cannot archive
+#[derive(Clone, Copy, PartialEq)]
+enum Special {
+    Fifo,
+    Socket,
+    Char,
+    Block
+}
+
+
+#[cfg(unix)]
+fn special_kind(path: & Path) -> Option<Special> {
+    use std::os::unix::fs::FileTypeExt;
+    let file_type = symlink_metadata(path).ok()?.file_type();
+    if file_type.is_fifo() {
+        Some(Special::Fifo)
+    } else if file_type.is_socket() {
+        Some(Special::Socket)
+    } else if file_type.is_char_device() {
+        Some(Special::Char)
+    } else if file_type.is_block_device() {
+        Some(Special::Block)
+    } else {
+        None
+    }
+}
+
+
+#[cfg(not(unix))]
+fn special_kind(_: & Path) -> Option<Special> {
+    None
+}
+
+
+// Turn a header filled from a FIFO's or device node's metadata into a
+// header-only entry (with the device numbers)
+#[cfg(unix)]
+fn set_special(
+        header: & mut Header, meta: & std::fs::Metadata, kind: Special
+    ) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    header.set_entry_type(match kind {
+        Special::Char => EntryType::Char,
+        Special::Block => EntryType::Block,
+        _ => EntryType::Fifo
+    });
+    if kind == Special::Char || kind == Special::Block {
+        // Pure bit arithmetic on the device number
+        let (major, minor) = unsafe {
+            (libc::major(meta.rdev()), libc::minor(meta.rdev()))
+        };
+        header.set_device_major(major)?;
+        header.set_device_minor(minor)?;
+    }
+    header.set_size(0);
+    Ok(())
+}
+
+
+#[cfg(not(unix))]
+fn set_special(
+        _: & mut Header, _: & std::fs::Metadata, _: Special
+    ) -> io::Result<()> {
+    Ok(())
+}
+
+
+// Put `name` into the header directly, first writing a GNU long name entry
+// if it does not fit. Unlike Header::set_path this accepts absolute names.
+fn set_name(
+        archive: & mut Builder<CountingWriter<ShardOutput>>,
+        header: & mut Header, name: & [u8]
+    ) -> io::Result<()> {
+    if name.len() > NAME_FIELD {
+        let mut long = Header::new_gnu();
+        long.as_gnu_mut().unwrap().name[..13].copy_from_slice(b"././@LongLink");
+        long.set_entry_type(EntryType::GNULongName);
+        long.set_mode(0o644);
+        long.set_size(name.len() as u64 + 1);
+        long.set_cksum();
+        archive.append(& long, name.chain(& [0u8][..]))?;
+    }
+    let len = name.len().min(NAME_FIELD);
+    header.as_old_mut().name[..len].copy_from_slice(& name[..len]);
+    Ok(())
+}
+
+
+// Archive `input` as a sparse entry named `name` if it is a file with holes,
+// preceded by a PAX header of `records`. Returns false (having written
+// nothing) otherwise.
+fn append_sparse(
+        archive: & mut Builder<CountingWriter<ShardOutput>>, input: & Path,
+        name: & Path, header_mode: HeaderMode,
+        records: & [(Vec<u8>, Vec<u8>)]
+    ) -> Result<bool, ArchiverError> {
+    let meta = symlink_metadata(input)?;
+    if ! meta.is_file() {
+        return Ok(false);
+    }
+    let mut file = File::open(input)?;
+    let regions = match sparse::data_regions(& file, & meta)? {
+        Some(regions) => regions,
+        None => return Ok(false)
+    };
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(& meta, header_mode);
+    append_pax(archive, records)?;
+    set_name(archive, & mut header, name.as_os_str().as_encoded_bytes())?;
+    sparse::write_sparse(archive.get_mut(), & mut header, & mut file, & regions)?;
+    Ok(true)
+}
+
+
+// Builder refuses absolute names, so with `absolute_names` the header is
+// filled in here (preceded by a PAX header of `records`)
+fn append_absolute(
+        archive: & mut Builder<CountingWriter<ShardOutput>>, input: & Path,
+        name: & Path, header_mode: HeaderMode,
+        records: & [(Vec<u8>, Vec<u8>)]
+    ) -> Result<(), ArchiverError> {
+    let meta = symlink_metadata(input)?;
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(& meta, header_mode);
+    if let Some(kind) = special_kind(input) {
+        set_special(& mut header, & meta, kind)?;
+    } else if meta.file_type().is_symlink() {
+        header.set_size(0);
+        header.set_link_name(read_link(input)?)?;
+    }
+    // Opened before anything is written: a long name entry left without its
+    // entry would apply to the next one
+    let data = if meta.is_file() { Some(File::open(input)?) } else { None };
+
+    append_pax(archive, records)?;
+    set_name(archive, & mut header, name.as_os_str().as_encoded_bytes())?;
+    header.set_cksum();
+
+    match data {
+        Some(file) => archive.append(& header, file)?,
+        None => archive.append(& header, io::empty())?
+    }
+    Ok(())
+}
+
+
+fn too_long(what: & str, value: & Path, format: Format) -> ArchiverError {
+    ArchiverError::Io(io::Error::other(format!(
+        "{} {} is too long for the {} format (use --format pax or gnu)",
+        what, value.display(), format
+    )))
+}
+
+
+// Archive `input` as `name` in a ustar header (a hard link to `hard_link` if
+// given), preceded with `pax` by a PAX header of `records` and of whatever
+// ustar cannot hold. Builder writes GNU headers for files, so these entries
+// are put together here.
+fn append_posix(
+        archive: & mut Builder<CountingWriter<ShardOutput>>, input: & Path,
+        name: & Path, hard_link: Option<& Path>, config: & ShardConfig,
+        mut records: Vec<(Vec<u8>, Vec<u8>)>
+    ) -> Result<(), ArchiverError> {
+    let pax = config.format == Format::Pax;
+    let meta = symlink_metadata(input)?;
+    let mut header = Header::new_ustar();
+    header.set_metadata_in_mode(& meta, config.header_mode);
+    let mut link_target = None;
+    if let Some(original) = hard_link {
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        link_target = Some(original.to_path_buf());
+    } else if let Some(kind) = special_kind(input) {
+        set_special(& mut header, & meta, kind)?;
+    } else if meta.file_type().is_symlink() {
+        header.set_size(0);
+        link_target = Some(read_link(input)?);
+    }
+
+    let name_bytes = name.as_os_str().as_encoded_bytes();
+    match split_ustar(name_bytes) {
+        Some((prefix, short)) => {
+            let ustar = header.as_ustar_mut().unwrap();
+            ustar.prefix[..prefix.len()].copy_from_slice(prefix);
+            ustar.name[..short.len()].copy_from_slice(short);
+        }
+        None if pax => {
+            records.push((b"path".to_vec(), name_bytes.to_vec()));
+            header.as_old_mut().name.copy_from_slice(& name_bytes[..NAME_FIELD]);
+        }
+        None => return Err(too_long("name", name, config.format))
+    }
+    if let Some(link_target) = link_target {
+        if header.set_link_name(& link_target).is_err() {
+            if ! pax {
+                return Err(too_long("link target", & link_target, config.format));
+            }
+            records.push((
+                b"linkpath".to_vec(),
+                link_target.as_os_str().as_encoded_bytes().to_vec()
+            ));
+        }
+    }
+    let has_data = meta.is_file() && hard_link.is_none();
+    // Beyond the 11 octal digits of the size field
+    if pax && has_data && meta.len() > 0o77777777777 {
+        records.push((b"size".to_vec(), meta.len().to_string().into_bytes()));
+    }
+    // Opened before anything is written: a PAX header left without its
+    // entry would apply to the next one
+    let data = if has_data { Some(File::open(input)?) } else { None };
+    if pax {
+        append_pax(archive, & records)?;
+    }
+    header.set_cksum();
+
+    match data {
+        Some(file) => archive.append(& header, file)?,
+        None => archive.append(& header, io::empty())?
+    }
+    Ok(())
+}
+
+
+// A member name as it is stored: relative, like tar does without -P
+fn stored_name<'a>(name: & 'a Path, config: & ShardConfig) -> & 'a Path {
+    if config.absolute_names {
+        name
+    } else {
+        name.strip_prefix("/").unwrap_or(name)
+    }
+}
+
+
+// A further name of a file archived as `original`: a Link entry, which
+// carries no data
+fn append_hard_link(
+        archive: & mut Builder<CountingWriter<ShardOutput>>, input: & Path,
+        name: & Path, original: & Path, header_mode: HeaderMode
+    ) -> Result<(), ArchiverError> {
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(& symlink_metadata(input)?, header_mode);
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    if name.is_absolute() || original.is_absolute() {
+        // Builder refuses absolute names
+        header.set_link_name(original)?;
+        set_name(archive, & mut header, name.as_os_str().as_encoded_bytes())?;
+        header.set_cksum();
+        archive.append(& header, io::empty())?;
+    } else {
+        archive.append_link(& mut header, name, original)?;
+    }
+    Ok(())
+}
+
+
+// Archive the file at `input` under `name` (`input`, unless transformed)
+fn append_item(
+        archive: & mut Builder<CountingWriter<ShardOutput>>, input: & Path,
+        name: & Path, config: & ShardConfig
+    ) -> Result<(), ArchiverError> {
+    let absolute = config.absolute_names && name.is_absolute();
+    let name = stored_name(name, config);
+    // A further name of a file archived earlier is linked to it (unless the
+    // transform left the earlier one out)
+    let original = config.hard_links.as_ref()
+        .and_then(|links| links.get(input))
+        .and_then(|original| member_name(original, config));
+    let original = original.as_deref().map(|name| stored_name(name, config));
+    if config.format != Format::Gnu {
+        // ustar has no place for extended attributes, and a link shares
+        // them with its original
+        let records = if config.xattrs && config.format == Format::Pax
+                && original.is_none() {
+            xattrs::records(input)?
+        } else {
+            Vec::new()
+        };
+        return append_posix(archive, input, name, original, config, records);
+    }
+    if let Some(original) = original {
+        return append_hard_link(
+            archive, input, name, original, config.header_mode
+        );
+    }
+    // Gathered first, but written only once the entry they precede can be:
+    // a PAX header left without its entry would apply to the next one
+    let records = if config.xattrs {
+        xattrs::records(input)?
+    } else {
+        Vec::new()
+    };
+    let header_mode = config.header_mode;
+    if config.sparse
+            && append_sparse(archive, input, name, header_mode, & records)? {
+        return Ok(());
+    }
+    if absolute {
+        return append_absolute(archive, input, name, header_mode, & records);
+    }
+    if let Some(kind) = special_kind(input) {
+        let meta = symlink_metadata(input)?;
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(& meta, config.header_mode);
+        set_special(& mut header, & meta, kind)?;
+        append_pax(archive, & records)?;
+        archive.append_data(& mut header, name, io::empty())?;
+    } else if is_symlink(input) {
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(
+            & symlink_metadata(input)?, config.header_mode
+        );
+        header.set_size(0);
+
+        let link_target = read_link(input)?;
+        let _ = header.set_link_name(& link_target);
+        append_pax(archive, & records)?;
+        archive.append_link(&mut header, name, & link_target)?;
+    } else if symlink_metadata(input)?.is_file() {
+        let mut file = File::open(input)?;
+        append_pax(archive, & records)?;
+        archive.append_file(name, & mut file)?;
+    } else {
+        append_pax(archive, & records)?;
+        archive.append_path_with_name(input, name)?;
+    }
+    Ok(())
+}
+
+
+// Whether `input` is a regular file larger than `max` bytes, checked just
+// before it is read (so a file that grew since the walk is caught too)
+fn oversized(input: & Path, max: Option<u64>) -> bool {
+    max.is_some_and(|max| match symlink_metadata(input) {
+        Ok(meta) => meta.is_file() && meta.len() > max,
+        Err(_) => false
+    })
+}
+
+
+// Whether the item is left out of the shard: sockets always (Tar cannot
+// represent them), FIFOs and device nodes with `skip_special`
+fn skip_item(input: & Path, skip_special: bool) -> bool {
+    match special_kind(input) {
+        Some(Special::Socket) => true,
+        Some(_) => skip_special,
+        None => false
+    }
+}
+
+
+// With `check_symlinks`, a symlink whose target does not exist is refused
+// like an unreadable file, instead of being archived as it is
+fn dangling_link(input: & Path) -> Option<ArchiverError> {
+    if ! is_symlink(input) || metadata(input).is_ok() {
+        return None;
+    }
+    let target = read_link(input).map_or(
+        "?".to_string(), |target| target.to_string_lossy().to_string()
+    );
+    Some(ArchiverError::Io(io::Error::other(format!(
+        "dangling symlink to {}", target
+    ))))
+}
+
+
+// The name `input` is archived under, or None if it is left out: relative to
+// the first stripped root it is in (the roots themselves are left out), or
+// below the name its target has relative to `relative_to`, then transformed
+fn member_name(input: & Path, config: & ShardConfig) -> Option<PathBuf> {
+    let mut path = input.to_path_buf();
+    let rest = config.strip_roots.iter()
+        .find_map(|root| input.strip_prefix(root).ok());
+    match rest {
+        Some(rest) if rest.as_os_str().is_empty() => return None,
+        Some(rest) => path = rest.to_path_buf(),
+        None => {}
+    }
+    let rebased = config.relative_roots.iter()
+        .find_map(|(root, name)| Some((input.strip_prefix(root).ok()?, name)));
+    match rebased {
+        // A target that is the base itself has no name of its own
+        Some((rest, name)) if rest.as_os_str().is_empty() => {
+            if name.as_os_str().is_empty() {
+                return None;
+            }
+            path = name.clone();
+        }
+        Some((rest, name)) => path = name.join(rest),
+        None => {}
+    }
+    match & config.transform {
+        Some(transform) => transform.map_path(& path),
+        None => Some(path)
+    }
+}
+
+
+// `path` made absolute with the directories it is in resolved, but not
+// itself: a target that is a symlink keeps its own name
+fn absolute_path(path: & Path) -> io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            Ok(canonicalize(parent)?.join(name))
+        }
+        // "/", "." or ".."
+        _ => canonicalize(path)
+    }
+}
+
+
+// A failing item is reported with its result and does not stop the worker
+fn create_worker_thread(
+        archive_name: & str, idx: u32,
+        rx: Arc<Mutex<Receiver<PathBuf>>>,
+        tx: Sender<Archived>,
+        completed: Arc<Mutex<bool>>,
+        config: ShardConfig, timing: Timing
+    ) -> Result<(ShardMetrics, Vec<PartChecksum>), ArchiverError> {
+
+    let mut shard = ShardWriter::new(archive_name, idx, config.clone())?;
+    let mut metrics = ShardMetrics {
+        shard: idx, files: 0, bytes: 0, active: Duration::ZERO
+    };
+    // Bytes the shard already held (when appending)
+    let initial = shard.written();
+
+    loop {
+        // Finish the shard with what it has so far
+        if interrupted() {
+            metrics.bytes = shard.written() - initial;
+            return Ok((metrics, shard.finish()?));
+        }
+        match take_mutex_try_many(
+                & rx, timing.max_tries, timing.retry_wait, & completed
+            ) {
+            Ok(input) => {
+                if skip_item(& input, config.skip_special) {
+                    tx.send(Archived {
+                        path: input, shard: idx, bytes: 0, error: None,
+                        skipped: true, dropped: false, oversized: false
+                    }).map_err(|e| ArchiverError::Worker(e.to_string()))?;
+                    continue;
+                }
+                if let Some(error) = config.check_symlinks
+                        .then(|| dangling_link(& input)).flatten() {
+                    tx.send(Archived {
+                        path: input, shard: idx, bytes: 0, error: Some(error),
+                        skipped: false, dropped: false, oversized: false
+                    }).map_err(|e| ArchiverError::Worker(e.to_string()))?;
+                    continue;
+                }
+                let name = match member_name(& input, & config) {
+                    Some(name) => name,
+                    None => {
+                        tx.send(Archived {
+                            path: input, shard: idx, bytes: 0, error: None,
+                            skipped: false, dropped: true, oversized: false
+                        }).map_err(|e| ArchiverError::Worker(e.to_string()))?;
+                        continue;
+                    }
+                };
+                // A followed symlink target is archived as what it points to
+                let source = if config.followed_roots.contains(& input) {
+                    canonicalize(& input)
+                } else {
+                    Ok(input.clone())
+                };
+                if let Ok(source) = & source {
+                    if oversized(source, config.max_file_size) {
+                        tx.send(Archived {
+                            path: input, shard: idx, bytes: 0, error: None,
+                            skipped: false, dropped: false, oversized: true
+                        }).map_err(|e| ArchiverError::Worker(e.to_string()))?;
+                        continue;
+                    }
+                }
+                let started = Instant::now();
+                let written = shard.written();
+                let archive = shard.next_member()?;
+                let error = source.map_err(ArchiverError::from).and_then(
+                    |source| append_item(archive, & source, & name, & config)
+                ).err();
+                // Used to check work that has been done
+                let bytes = shard.written() - written;
+                if error.is_none() {
+                    metrics.files += 1;
+                    shard.list_member(stored_name(& name, & config));
+                }
+                metrics.active += started.elapsed();
+                tx.send(Archived {
+                    path: input, shard: idx, bytes, error, skipped: false,
+                    dropped: false, oversized: false
+                })
+                    .map_err(|e| ArchiverError::Worker(e.to_string()))?;
+            }
+            Err(error) => {
+                // Check if work is done
+                if get_mutex(& completed) || interrupted() {
+                    metrics.bytes = shard.written() - initial;
+                    return Ok((metrics, shard.finish()?));
+                }
+
+                return Err(ArchiverError::Worker(format!(
+                    "{} on thread responsible for: {}", error, shard.path()
+                )));
+            }
+        }
+    }
+}
+
+
+// Wait for all workers, returning the first error any of them reported
+fn join_workers<T>(
+        handles: Vec<JoinHandle<Result<T, ArchiverError>>>
+    ) -> Result<Vec<T>, ArchiverError> {
+    let mut results: Vec<T> = Vec::new();
+    let mut failure: Option<ArchiverError> = None;
+    for h in handles {
+        match h.join() {
+            Ok(Ok(result)) => results.push(result),
+            Ok(Err(error)) => {
+                failure.get_or_insert(error);
+            }
+            Err(_) => {
+                failure.get_or_insert(
+                    ArchiverError::Worker("worker thread panicked".to_string())
+                );
+            }
+        }
+    }
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(results)
+    }
+}
+
+
+// Unprocessed work items listed by run_workers; the count covers the rest
+const MAX_UNPROCESSED_LISTED: usize = 10;
+
+
+// Case collisions listed by create; the count covers the rest
+const MAX_COLLISIONS_LISTED: usize = 20;
+
+
+// Pairs of work items whose paths differ only in case (the earlier item
+// first). A case-insensitive file system cannot hold both: extracting there
+// writes one over the other.
+fn case_collisions(work_items: & [PathBuf]) -> Vec<(& PathBuf, & PathBuf)> {
+    let mut seen: HashMap<String, & PathBuf> = HashMap::new();
+    let mut collisions = Vec::new();
+    for item in work_items {
+        let folded = item.to_string_lossy().to_lowercase();
+        match seen.get(& folded) {
+            Some(first) => collisions.push((* first, item)),
+            None => {
+                seen.insert(folded, item);
+            }
+        }
+    }
+    collisions
+}
+
+
+// Archive `work_items` into one shard per entry of `shards`, with a worker
+// thread per shard. `distribution` routes each item to a shard (a shared
+// distribution lets the workers pull from a common queue). The progress
+// callback of `opts` is called about once a second while the workers run.
+// Archived items are recorded in `journal`, whose shards (if it is from an
+// earlier run) are continued.
+fn run_workers(
+        archive_name: & str, shards: & [u32], work_items: & [PathBuf],
+        distribution: Distribution, opts: & ArchiveOptions,
+        mut journal: Option<& mut Journal>
+    ) -> Result<CreateReport, ArchiverError> {
+    let mut config = opts.shard_config();
+    config.resume = journal.as_ref().is_some_and(|j| j.resumed);
+    if opts.preserve_hardlinks {
+        config.hard_links = Some(Arc::new(find_hard_links(work_items)));
+    }
+    config.relative_roots = opts.relative_roots()?;
+    let timing = opts.timing;
+
+    // Create channels for sending work and receiving results. A shared
+    // distribution uses one work channel for all workers, otherwise every
+    // worker gets its own.
+    let num_shards = shards.len() as u32;
+    let num_channels = if distribution.is_shared() { 1 } else { num_shards };
+    let mut tx_work: Vec<Sender<PathBuf>> = Vec::new();
+    let mut rx_work: Vec<Arc<Mutex<Receiver<PathBuf>>>> = Vec::new();
+    for _ in 0..num_channels {
+        let (tx, rx) = channel();
+        tx_work.push(tx);
+        rx_work.push(Arc::new(Mutex::new(rx)));
+    }
+    let (tx_results, rx_results) = channel();
+    // Used to signal threads to shut down (once work is complete)
+    let work_completed = Arc::new(Mutex::new(false));
+
+    // Spawn worker threads
+    status!(archive_name, "Starting {} worker threads", num_shards);
+    let mut handles = Vec::new();
+    for (pos, idx) in shards.iter().enumerate() {
+        let rx = Arc::clone(& rx_work[pos % rx_work.len()]);
+        let tx = tx_results.clone();
+        let cmp = Arc::clone(& work_completed);
+        let name = archive_name.to_string();
+        let config = config.clone();
+        let idx = * idx;
+        handles.push(
+            thread::spawn(move || {
+                let path = if name == STDOUT {
+                    name.clone()
+                } else {
+                    config.names.path(& name, idx, 0)
+                };
+                create_worker_thread(
+                    name.as_str(), idx, rx, tx, cmp, config, timing
+                ).map_err(|e| e.in_shard(& path))
+            })
+        );
+    }
+
+    // Only the workers hold result senders from here on, so collection
+    // notices when they have all exited
+    drop(tx_results);
+
+    // Add work to the work channel(s). A worker that failed has dropped its
+    // receiver (its failure is reported when it is joined), so its files are
+    // no longer sent, and dispatch stops once no worker is left.
+    // When every worker has its own channel, items are sent in sorted order,
+    // so members are appended to each shard in path order whatever order the
+    // work list is in (or how the workers are scheduled).
+    let mut order: Vec<& PathBuf> = work_items.iter().collect();
+    if ! distribution.is_shared() {
+        order.sort();
+    }
+    let mut shard_counts = vec![0usize; num_channels as usize];
+    let mut gone = vec![false; num_channels as usize];
+    for work_item in order {
+        let pos = distribution.shard_of(work_item, num_channels);
+        if gone[pos] {
+            continue;
+        }
+        if tx_work[pos].send(work_item.clone()).is_err() {
+            gone[pos] = true;
+            if gone.iter().all(|& g| g) {
+                eprintln!("All workers are gone, aborting dispatch");
+                break;
+            }
+            continue;
+        }
+        shard_counts[pos] += 1;
+    }
+    if ! distribution.is_shared() {
+        for (pos, ct) in shard_counts.iter().enumerate() {
+            status!(archive_name, "Shard {} was assigned {} files", shards[pos], ct);
+        }
+    }
+
+    status!(archive_name, "Collecting worker status (workers are working) ...");
+    let mut tracker = Tracker::new(
+        opts.progress.clone(), shards, work_items.len()
+    );
+    let mut journal_error = None;
+    let collected = collect_expected(
+        work_items.len(), rx_results, timing.collect_timeout,
+        |result: Option<& Archived>| {
+            if let Some(archived) = result {
+                tracker.record(archived.shard, archived.bytes);
+                let archived_ok = archived.error.is_none() && ! archived.skipped
+                    && ! archived.dropped && ! archived.oversized;
+                // The worker has written the item before sending its result
+                if let Some(journal) = journal.as_deref_mut() {
+                    let recorded = if archived_ok {
+                        journal.record(
+                            archived.shard,
+                            archived.path.as_os_str().as_encoded_bytes()
+                        )
+                    } else {
+                        Ok(())
+                    };
+                    if let Err(error) = recorded {
+                        journal_error.get_or_insert(error);
+                    }
+                }
+                // On stderr, like progress: stdout may be the archive
+                if opts.verbose && archived_ok {
+                    eprintln!(
+                        "shard {}: {}", archived.shard, archived.path.display()
+                    );
+                }
+            }
+            tracker.tick();
+        }
+    );
+    tracker.finish();
+    set_mutex(& work_completed, true);
+
+    status!(archive_name, " ... waiting for workers to finish ...");
+    // A worker's own error explains a collection timeout better than the
+    // timeout does, so it is reported first
+    let mut metrics: Vec<ShardMetrics> = Vec::new();
+    let mut checksums: Vec<PartChecksum> = Vec::new();
+    for (shard_metrics, shard_checksums) in join_workers(handles)? {
+        metrics.push(shard_metrics);
+        checksums.extend(shard_checksums);
+    }
+    if let Some(error) = journal_error {
+        return Err(error.into());
+    }
+    let mut processed_items: Vec<PathBuf> = Vec::new();
+    let mut failed: PathErrors = Vec::new();
+    let mut skipped: Vec<PathBuf> = Vec::new();
+    let mut dropped: Vec<PathBuf> = Vec::new();
+    let mut oversized: Vec<PathBuf> = Vec::new();
+    // Work items no result has come back for, crossed off as results are
+    // sorted, so that checking for lost items needs no further pass
+    let mut outstanding: HashSet<& PathBuf> = work_items.iter().collect();
+    for archived in collected? {
+        outstanding.remove(& archived.path);
+        match archived.error {
+            _ if archived.skipped => skipped.push(archived.path),
+            _ if archived.dropped => dropped.push(archived.path),
+            _ if archived.oversized => oversized.push(archived.path),
+            None => processed_items.push(archived.path),
+            Some(error) => failed.push((archived.path, error))
+        }
+    }
+    status!(archive_name, " ... workers are done ...");
+    drop(tx_work);
+
+    let interrupted = interrupted();
+    if interrupted {
+        status!(
+            archive_name,
+            "... interrupted after {} of {} files.",
+            processed_items.len(), work_items.len()
+        );
+    } else {
+        status!(archive_name, "... checking worker status.");
+        if ! outstanding.is_empty() {
+            status!(
+                archive_name, "{} work items requested but not processed!",
+                format_count(outstanding.len() as u64)
+            );
+            let sample = work_items.iter()
+                .filter(|item| outstanding.contains(item))
+                .take(MAX_UNPROCESSED_LISTED);
+            for item in sample {
+                status!(archive_name, "  {}", item.display());
+            }
+        }
+    }
+
+    Ok(CreateReport {
+        processed: processed_items,
+        failed,
+        skipped,
+        dropped,
+        oversized,
+        unreadable: Vec::new(),
+        checksums,
+        shards: metrics,
+        interrupted,
+        elapsed: Duration::ZERO
+    })
+}
+
+
+// Write `checksums` in the `<hex>  <filename>` format of `sha256sum`. File
+// names are stored relative to the sums file, so `sha256sum -c` works when
+// run next to the shards.
+fn write_checksums(
+        path: & str, checksums: & [PartChecksum]
+    ) -> Result<(), ArchiverError> {
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut text = String::new();
+    for c in checksums {
+        let part = Path::new(& c.path);
+        let name = part.strip_prefix(dir).unwrap_or(part).to_string_lossy();
+        text.push_str(& format!("{}  {}\n", c.sha256, name));
+    }
+    write(path, text)?;
+    println!("Wrote checksums of {} shard files to {}", checksums.len(), path);
+    Ok(())
+}
+
+
+fn header_mode(deterministic: bool) -> HeaderMode {
+    if deterministic {
+        HeaderMode::Deterministic
+    } else {
+        HeaderMode::Complete
+    }
+}
+
+
+// What to archive and how. Built with `ArchiveOptions::new(target)` and the
+// chainable setters below, or as a struct literal over
+// `ArchiveOptions::default()`; the defaults match the CLI's.
+#[derive(Clone)]
+pub struct ArchiveOptions {
+    // Directory to archive (create, plan) or to extract into (extract)
+    pub target: String,
+    // Further directories or files create archives along with `target`,
+    // each under its own name like `target` (see `strip_root`)
+    pub more_targets: Vec<String>,
+    pub num_threads: u32,
+    pub follow_links: bool,
+    // Follow targets that are symlinks (storing what they point to under
+    // their own name), but archive the symlinks below them as links
+    pub follow_toplevel: bool,
+    // Globs of paths to skip, see `ExcludeSet`
+    pub excludes: Vec<String>,
+    // Byte-identical shards across runs: the work list is sorted, files are
+    // placed by a stable hash of their path, and headers are written in
+    // HeaderMode::Deterministic. The price is that mtimes, ownership and
+    // most permission bits are not preserved, and shards are only balanced
+    // by path, not by size or worker speed.
+    pub deterministic: bool,
+    // Keep files from the same directory in the same shard (by hashing the
+    // parent directory), trading size balance for restore locality
+    pub group_dirs: bool,
+    // Place files by a stable hash of their path (as in `deterministic`,
+    // but with complete headers), so a file lands in the same shard on every
+    // run; each worker gets its own queue
+    pub stable_placement: bool,
+    // Split a worker's shard into several parts once a part holds too many
+    // entries or too many bytes
+    pub limits: ShardLimits,
+    // Write `{archive_name}.SHA256SUMS` for the produced shard files,
+    // checkable with `sha256sum -c`
+    pub checksums: bool,
+    // Called about once a second during create
+    pub progress: Option<Arc<ProgressFn>>,
+    // Archive the paths listed in this file ("-" for stdin) instead of
+    // walking `target`. Excludes are not applied to the list.
+    pub files_from: Option<String>,
+    // What extract does with entries whose destination already exists
+    pub on_conflict: ConflictPolicy,
+    // Leading path components extract drops from every entry
+    pub strip_components: usize,
+    // Extract every file directly into the target, by file name (name
+    // collisions are conflicts, see `on_conflict`)
+    pub flatten: bool,
+    // Extract only regular files and directories (and hard links), leaving
+    // out devices, FIFOs and symlinks
+    pub regular_only: bool,
+    // Extract only the entries at or below this path in the archive
+    // (leading "/" and "./" do not matter)
+    pub subdir: Option<String>,
+    // Extract hard links as independent copies of their originals
+    pub dereference_hardlinks: bool,
+    // Extract files and directories with their archived modes; otherwise
+    // the modes are masked with the umask
+    pub same_permissions: bool,
+    // Threads writing the files of each shard during extract. Above 1, whole
+    // files are buffered in memory between the reading thread and the
+    // writers.
+    pub parallel_entries: u32,
+    // Do not descend into directories on other file systems while walking
+    // the target
+    pub one_file_system: bool,
+    // Warn about paths that differ only in case (and so cannot both be
+    // extracted onto a case-insensitive file system) before archiving
+    pub check_case: bool,
+    // Add to existing shards (continuing their last part) instead of
+    // replacing them. Shards that do not exist yet are created.
+    pub append: bool,
+    pub timing: Timing,
+    // Report files that cannot be archived in CreateReport::failed instead
+    // of failing create
+    pub ignore_errors: bool,
+    // Archive files with identical contents once, recording the other paths
+    // in `{archive_name}.dedup.json` (see `dedup`)
+    pub dedup: bool,
+    // Leave FIFOs and device nodes out instead of archiving them (as
+    // header-only entries)
+    pub skip_special: bool,
+    // Store absolute paths as they are instead of dropping the leading "/"
+    pub absolute_names: bool,
+    // Archive extended attributes (as PAX records) and restore them on
+    // extract; nothing to do on platforms without them
+    pub xattrs: bool,
+    // Store only the data of files with holes (see `sparse`)
+    pub sparse: bool,
+    // Names of the shard files, for create and extract
+    pub name_template: NameTemplate,
+    // Directories the shards are spread over, round-robin (otherwise they
+    // are next to `archive_name`); extract needs the same list
+    pub shard_dirs: Vec<String>,
+    // Report symlinks to targets that do not exist as failed items (see
+    // `ignore_errors`) instead of archiving them
+    pub check_symlinks: bool,
+    // Print every archived path (with its shard) to stderr, like `tar -v`
+    pub verbose: bool,
+    // Fail create at the first path the walk cannot read, instead of leaving
+    // it out (see CreateReport::unreadable)
+    pub fail_fast: bool,
+    // Header format of the shards (see `format`); sparse entries are only
+    // written in the gnu format
+    pub format: Format,
+    // Leave out regular files larger (or smaller) than this many bytes,
+    // from the walk and from the `files_from` list alike
+    pub exclude_larger_than: Option<u64>,
+    // Safety limit: a regular file larger than this many bytes when a
+    // worker comes to archive it is left out with a warning (see
+    // CreateReport::oversized), and never hashed by `dedup`
+    pub max_file_size: Option<u64>,
+    pub exclude_smaller_than: Option<u64>,
+    // Renames (or leaves out) every item just before it is archived
+    pub transform: Option<Arc<dyn EntryTransform + Send + Sync>>,
+    // Store the contents of directory targets at the top level of the
+    // archive (`foo.txt`, not `target/foo.txt`), before any `transform`.
+    // With several targets their contents are merged.
+    pub strip_root: bool,
+    // Store every target under its path relative to this directory, e.g.
+    // `/data/project/sub` as `project/sub` with `/data`, wherever create is
+    // run from. Every target has to be below it.
+    pub relative_to: Option<String>,
+    // Before archiving, check that the shards' file systems have room for
+    // the estimated shards plus this many percent (Unix only)
+    pub check_space: Option<u32>,
+    // Record archived items here, and resume the run it is left over from
+    // (see `journal`). Removed once create has archived everything.
+    pub journal: Option<String>,
+    // Archive every further name of a file (in the same shard or another)
+    // as a hard link to the first, instead of storing the file again
+    pub preserve_hardlinks: bool,
+    // fsync every shard part once it is finished, so that the shards
+    // survive a crash right after create returns. Costs a disk flush per
+    // part; off by default.
+    pub fsync: bool,
+    // End every shard part with an index entry listing its members (see
+    // shard::SHARD_INDEX), so that a part describes itself
+    pub shard_index: bool
+}
+
+
+impl Default for ArchiveOptions {
+    fn default() -> ArchiveOptions {
+        ArchiveOptions {
+            target: String::new(),
+            more_targets: Vec::new(),
+            num_threads: 1,
+            follow_links: false,
+            follow_toplevel: false,
+            excludes: Vec::new(),
+            deterministic: false,
+            group_dirs: false,
+            stable_placement: false,
+            limits: ShardLimits::default(),
+            checksums: false,
+            progress: None,
+            files_from: None,
+            on_conflict: ConflictPolicy::default(),
+            strip_components: 0,
+            flatten: false,
+            regular_only: false,
+            subdir: None,
+            dereference_hardlinks: false,
+            same_permissions: true,
+            parallel_entries: 1,
+            one_file_system: false,
+            check_case: false,
+            append: false,
+            timing: Timing::default(),
+            ignore_errors: false,
+            dedup: false,
+            skip_special: false,
+            absolute_names: false,
+            xattrs: false,
+            sparse: false,
+            name_template: NameTemplate::default(),
+            shard_dirs: Vec::new(),
+            check_symlinks: false,
+            verbose: false,
+            fail_fast: false,
+            format: Format::default(),
+            exclude_larger_than: None,
+            max_file_size: None,
+            exclude_smaller_than: None,
+            transform: None,
+            strip_root: false,
+            relative_to: None,
+            check_space: None,
+            journal: None,
+            preserve_hardlinks: false,
+            fsync: false,
+            shard_index: false
+        }
+    }
+}
+
+
+impl ArchiveOptions {
+    pub fn new(target: & str) -> ArchiveOptions {
+        ArchiveOptions {
+            target: target.to_string(),
+            ..ArchiveOptions::default()
+        }
+    }
+
+    pub fn more_targets(mut self, targets: & [String]) -> ArchiveOptions {
+        self.more_targets.extend_from_slice(targets);
+        self
+    }
+
+    pub fn num_threads(mut self, num_threads: u32) -> ArchiveOptions {
+        self.num_threads = num_threads;
+        self
+    }
+
+    pub fn follow_links(mut self, follow_links: bool) -> ArchiveOptions {
+        self.follow_links = follow_links;
+        self
+    }
+
+    pub fn follow_toplevel(mut self, follow: bool) -> ArchiveOptions {
+        self.follow_toplevel = follow;
+        self
+    }
+
+    pub fn one_file_system(mut self, one_file_system: bool) -> ArchiveOptions {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    pub fn check_case(mut self, check_case: bool) -> ArchiveOptions {
+        self.check_case = check_case;
+        self
+    }
+
+    pub fn append(mut self, append: bool) -> ArchiveOptions {
+        self.append = append;
+        self
+    }
+
+    pub fn timing(mut self, timing: Timing) -> ArchiveOptions {
+        self.timing = timing;
+        self
+    }
+
+    pub fn ignore_errors(mut self, ignore_errors: bool) -> ArchiveOptions {
+        self.ignore_errors = ignore_errors;
+        self
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> ArchiveOptions {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn absolute_names(mut self, absolute_names: bool) -> ArchiveOptions {
+        self.absolute_names = absolute_names;
+        self
+    }
+
+    pub fn exclude_larger_than(mut self, max: Option<u64>) -> ArchiveOptions {
+        self.exclude_larger_than = max;
+        self
+    }
+
+    pub fn max_file_size(mut self, max: Option<u64>) -> ArchiveOptions {
+        self.max_file_size = max;
+        self
+    }
+
+    pub fn exclude_smaller_than(mut self, min: Option<u64>) -> ArchiveOptions {
+        self.exclude_smaller_than = min;
+        self
+    }
+
+    pub fn journal(mut self, path: Option<& str>) -> ArchiveOptions {
+        self.journal = path.map(|p| p.to_string());
+        self
+    }
+
+    pub fn preserve_hardlinks(mut self, preserve: bool) -> ArchiveOptions {
+        self.preserve_hardlinks = preserve;
+        self
+    }
+
+    pub fn fsync(mut self, fsync: bool) -> ArchiveOptions {
+        self.fsync = fsync;
+        self
+    }
+
+    pub fn shard_index(mut self, shard_index: bool) -> ArchiveOptions {
+        self.shard_index = shard_index;
+        self
+    }
+
+    pub fn strip_root(mut self, strip_root: bool) -> ArchiveOptions {
+        self.strip_root = strip_root;
+        self
+    }
+
+    pub fn relative_to(mut self, base: Option<String>) -> ArchiveOptions {
+        self.relative_to = base;
+        self
+    }
+
+    pub fn check_space(mut self, margin: Option<u32>) -> ArchiveOptions {
+        self.check_space = margin;
+        self
+    }
+
+    pub fn transform(
+            mut self, transform: Box<dyn EntryTransform + Send + Sync>
+        ) -> ArchiveOptions {
+        self.transform = Some(Arc::from(transform));
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> ArchiveOptions {
+        self.format = format;
+        self
+    }
+
+    pub fn fail_fast(mut self, fail_fast: bool) -> ArchiveOptions {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> ArchiveOptions {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn check_symlinks(mut self, check_symlinks: bool) -> ArchiveOptions {
+        self.check_symlinks = check_symlinks;
+        self
+    }
+
+    pub fn shard_dirs(mut self, dirs: & [String]) -> ArchiveOptions {
+        self.shard_dirs.extend_from_slice(dirs);
+        self
+    }
+
+    pub fn name_template(mut self, template: NameTemplate) -> ArchiveOptions {
+        self.name_template = template;
+        self
+    }
+
+    pub fn sparse(mut self, sparse: bool) -> ArchiveOptions {
+        self.sparse = sparse;
+        self
+    }
+
+    pub fn xattrs(mut self, xattrs: bool) -> ArchiveOptions {
+        self.xattrs = xattrs;
+        self
+    }
+
+    pub fn skip_special(mut self, skip_special: bool) -> ArchiveOptions {
+        self.skip_special = skip_special;
+        self
+    }
+
+    pub fn exclude(mut self, glob: & str) -> ArchiveOptions {
+        self.excludes.push(glob.to_string());
+        self
+    }
+
+    pub fn excludes(mut self, globs: & [String]) -> ArchiveOptions {
+        self.excludes.extend_from_slice(globs);
+        self
+    }
+
+    // Add VCS_EXCLUDES to the excludes
+    pub fn exclude_vcs(mut self, exclude_vcs: bool) -> ArchiveOptions {
+        if exclude_vcs {
+            self.excludes.extend(VCS_EXCLUDES.iter().map(|g| g.to_string()));
+        }
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> ArchiveOptions {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn group_dirs(mut self, group_dirs: bool) -> ArchiveOptions {
+        self.group_dirs = group_dirs;
+        self
+    }
+
+    pub fn stable_placement(mut self, stable: bool) -> ArchiveOptions {
+        self.stable_placement = stable;
+        self
+    }
+
+    pub fn limits(mut self, limits: ShardLimits) -> ArchiveOptions {
+        self.limits = limits;
+        self
+    }
+
+    pub fn checksums(mut self, checksums: bool) -> ArchiveOptions {
+        self.checksums = checksums;
+        self
+    }
+
+    pub fn files_from(mut self, source: Option<& str>) -> ArchiveOptions {
+        self.files_from = source.map(|s| s.to_string());
+        self
+    }
+
+    pub fn on_conflict(mut self, policy: ConflictPolicy) -> ArchiveOptions {
+        self.on_conflict = policy;
+        self
+    }
+
+    pub fn strip_components(mut self, strip: usize) -> ArchiveOptions {
+        self.strip_components = strip;
+        self
+    }
+
+    pub fn flatten(mut self, flatten: bool) -> ArchiveOptions {
+        self.flatten = flatten;
+        self
+    }
+
+    pub fn regular_only(mut self, regular_only: bool) -> ArchiveOptions {
+        self.regular_only = regular_only;
+        self
+    }
+
+    pub fn subdir(mut self, subdir: Option<String>) -> ArchiveOptions {
+        self.subdir = subdir;
+        self
+    }
+
+    pub fn dereference_hardlinks(mut self, copy: bool) -> ArchiveOptions {
+        self.dereference_hardlinks = copy;
+        self
+    }
+
+    pub fn same_permissions(mut self, same: bool) -> ArchiveOptions {
+        self.same_permissions = same;
+        self
+    }
+
+    pub fn parallel_entries(mut self, threads: u32) -> ArchiveOptions {
+        self.parallel_entries = threads;
+        self
+    }
+
+    pub fn progress(
+            mut self, callback: impl Fn(& Progress) + Send + Sync + 'static
+        ) -> ArchiveOptions {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    // Where the shard files are: the template, plus the shard directories
+    fn shard_names(& self) -> NameTemplate {
+        self.name_template.clone().with_dirs(& self.shard_dirs)
+    }
+
+    fn targets(& self) -> Vec<& str> {
+        let mut targets = vec![self.target.as_str()];
+        targets.extend(self.more_targets.iter().map(|t| t.as_str()));
+        targets
+    }
+
+    // The targets that are directories (the ones `strip_root` applies to)
+    fn dir_targets(& self) -> Vec<& str> {
+        self.targets().into_iter()
+            .filter(|target| Path::new(target).is_dir())
+            .collect()
+    }
+
+    // Every target and the name it is stored under with `relative_to`. Fails
+    // with InvalidArg for a target that is not below the base (once both
+    // are absolute, with symlinked directories resolved).
+    fn relative_roots(
+            & self
+        ) -> Result<Vec<(PathBuf, PathBuf)>, ArchiverError> {
+        let base = match & self.relative_to {
+            Some(base) => canonicalize(base)?,
+            None => return Ok(Vec::new())
+        };
+        let mut roots = Vec::new();
+        for target in self.targets() {
+            let name = match absolute_path(Path::new(target))?
+                    .strip_prefix(& base) {
+                Ok(name) => name.to_path_buf(),
+                Err(_) => return Err(ArchiverError::InvalidArg(format!(
+                    "{} is not below --relative-to {}",
+                    target, base.display()
+                )))
+            };
+            roots.push((PathBuf::from(target), name));
+        }
+        Ok(roots)
+    }
+
+    fn shard_config(& self) -> ShardConfig {
+        ShardConfig {
+            header_mode: header_mode(self.deterministic),
+            limits: self.limits,
+            checksums: self.checksums,
+            append: self.append,
+            skip_special: self.skip_special,
+            absolute_names: self.absolute_names,
+            xattrs: self.xattrs,
+            sparse: self.sparse,
+            names: self.shard_names(),
+            check_symlinks: self.check_symlinks,
+            format: self.format,
+            transform: self.transform.clone(),
+            strip_roots: self.dir_targets().into_iter()
+                .filter(|_| self.strip_root)
+                .map(PathBuf::from)
+                .collect(),
+            resume: false,
+            hard_links: None,
+            max_file_size: self.max_file_size,
+            fsync: self.fsync,
+            relative_roots: Vec::new(),
+            followed_roots: self.targets().into_iter()
+                .filter(|_| self.follow_toplevel)
+                .filter(|target| is_symlink(Path::new(target)))
+                .map(PathBuf::from)
+                .collect(),
+            shard_index: self.shard_index
+        }
+    }
+
+    fn extract_config(& self) -> ExtractConfig {
+        ExtractConfig {
+            policy: self.on_conflict,
+            strip: self.strip_components,
+            entry_threads: self.parallel_entries,
+            xattrs: self.xattrs,
+            flatten: self.flatten,
+            umask: (! self.same_permissions).then(process_umask),
+            regular_only: self.regular_only,
+            subdir: self.subdir.as_deref()
+                .and_then(|subdir| strip_path(Path::new(subdir), 0)),
+            dereference_hardlinks: self.dereference_hardlinks,
+            journal: None
+        }
+    }
+}
+
+
+// A set of shards named after `archive_name`, created from or extracted to
+// `options.target`.
+pub struct Archiver {
+    archive_name: String,
+    options: ArchiveOptions,
+    excludes: ExcludeSet
+}
+
+
+impl Archiver {
+    pub fn new(archive_name: & str, options: ArchiveOptions) -> Archiver {
+        Archiver {
+            archive_name: archive_name.to_string(),
+            excludes: ExcludeSet::new(& options.excludes),
+            options
+        }
+    }
+
+    pub fn options(& self) -> & ArchiveOptions {
+        & self.options
+    }
+
+    // Unless errors are ignored, turn failed items into an error. The shards
+    // (and sums and fragment) are complete either way.
+    fn check_failed(
+            & self, mut report: CreateReport
+        ) -> Result<CreateReport, ArchiverError> {
+        if report.failed.is_empty() || self.options.ignore_errors {
+            return Ok(report);
+        }
+        Err(ArchiverError::Failed(std::mem::take(& mut report.failed)))
+    }
+
+    // Fail if a file system the shards go to has less room than they are
+    // estimated to take (see estimated_size), plus `margin` percent. The
+    // estimate is shared out over the file systems by how many of `shards`
+    // each one holds.
+    fn check_space(
+            & self, work_items: & [PathBuf], shards: & [u32], margin: u32
+        ) -> Result<(), ArchiverError> {
+        let total = estimated_size(work_items) as f64
+            * (1.0 + margin as f64 / 100.0);
+        let names = self.options.shard_names();
+        // Shards per file system, and a directory on it
+        let mut file_systems: HashMap<Option<u64>, (PathBuf, u32)> =
+            HashMap::new();
+        for idx in shards {
+            let dir = existing_dir(Path::new(
+                & names.path(& self.archive_name, * idx, 0)
+            ));
+            let device = device_id(& metadata(& dir)?);
+            file_systems.entry(device).or_insert((dir, 0)).1 += 1;
+        }
+        for (dir, count) in file_systems.into_values() {
+            let needed = (total * count as f64 / shards.len() as f64) as u64;
+            let free = match free_space(& dir) {
+                Ok(free) => free,
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    eprintln!("Warning: not checking free space: {}", e);
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into())
+            };
+            if free < needed {
+                return Err(ArchiverError::Io(io::Error::new(
+                    io::ErrorKind::StorageFull, format!(
+                        "not enough space for {} shards in {}: about {} \
+                        needed ({}% margin included), {} free",
+                        count, dir.display(), format_size(needed), margin,
+                        format_size(free)
+                    )
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Without a thread there would be no worker to archive (or extract)
+    // anything, and create would wait for results that never come
+    fn check_threads(& self) -> Result<(), ArchiverError> {
+        if self.options.num_threads == 0 {
+            return Err(ArchiverError::InvalidArg(
+                "num_threads must be at least 1".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    // The index entry is marked by a PAX record, which ustar has no room
+    // for. Shards that are appended to (or resumed) would keep their old
+    // index in the middle and end with one of the new members only.
+    fn check_shard_index(& self) -> Result<(), ArchiverError> {
+        let opts = & self.options;
+        if ! opts.shard_index {
+            return Ok(());
+        }
+        if opts.format == Format::Ustar {
+            return Err(ArchiverError::InvalidArg(
+                "shard_index needs the gnu or pax format".to_string()
+            ));
+        }
+        if opts.append || opts.journal.is_some() {
+            return Err(ArchiverError::InvalidArg(
+                "shard_index cannot be combined with append or journal"
+                    .to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    // Write the references whose original was archived; the others are
+    // reported as failed, since their contents are not in any shard
+    fn save_references(
+            & self, references: References, report: & mut CreateReport
+        ) -> Result<(), ArchiverError> {
+        let archived: HashSet<& PathBuf> = report.processed.iter().collect();
+        let mut saved = References::default();
+        // Under the names they were archived with
+        let config = self.options.shard_config();
+        let rename = |path: & Path| member_name(path, & config);
+        for r in references.references {
+            if archived.contains(& r.original) {
+                if let (Some(path), Some(original)) =
+                        (rename(& r.path), rename(& r.original)) {
+                    saved.references.push(Reference {
+                        path, original, stat: r.stat
+                    });
+                }
+            } else {
+                let error = io::Error::other(format!(
+                    "duplicate of {}, which was not archived",
+                    r.original.display()
+                ));
+                report.failed.push((r.path, ArchiverError::Io(error)));
+            }
+        }
+        let path = dedup_path(& self.archive_name);
+        status!(
+            self.archive_name, "{} duplicate files recorded in {}",
+            saved.references.len(), path
+        );
+        saved.save(& path)?;
+        Ok(())
+    }
+
+    // Every target walked in turn. A path reached from more than one target
+    // (nested targets) is only listed the first time.
+    fn find_files(
+            & self, mut unreadable: Option<& mut PathErrors>
+        ) -> Result<Vec<PathBuf>, ArchiverError> {
+        let targets = self.options.targets();
+        let mut files = Vec::new();
+        for target in & targets {
+            files.extend(find_files(
+                target, self.options.follow_links, self.options.follow_toplevel,
+                & self.excludes, self.options.one_file_system,
+                unreadable.as_deref_mut()
+            )?);
+        }
+        if targets.len() > 1 {
+            let mut seen = HashSet::new();
+            files.retain(|path| seen.insert(path.clone()));
+        }
+        Ok(files)
+    }
+
+    // Canonical paths of the existing files create is about to overwrite or
+    // append to: the shard parts, the journal and the sums and references
+    // files
+    fn own_files(& self) -> HashSet<PathBuf> {
+        if self.archive_name == STDOUT {
+            return HashSet::new();
+        }
+        let mut outputs = vec![
+            format!("{}.SHA256SUMS", self.archive_name),
+            dedup_path(& self.archive_name)
+        ];
+        outputs.extend(self.options.journal.clone());
+        for idx in 0..self.options.num_threads {
+            outputs.extend(
+                self.options.shard_names().parts(& self.archive_name, idx)
+            );
+        }
+        outputs.iter().filter_map(|path| canonicalize(path).ok()).collect()
+    }
+
+    // Whether the size limits leave out a regular file of `size` bytes
+    fn excluded_size(& self, size: u64) -> bool {
+        self.options.exclude_larger_than.is_some_and(|max| size > max)
+            || self.options.exclude_smaller_than.is_some_and(|min| size < min)
+    }
+
+    // What create would archive, in the order it hands the items out: the
+    // `files_from` list, or the target walked with the excludes, ignore
+    // files and link options applied (sorted in deterministic mode), less
+    // the regular files outside the size limits. When the archive is written
+    // into the target, its own shards from an earlier run are left out too:
+    // they would be read while being replaced.
+    pub fn enumerate(& self) -> Result<WorkList, ArchiverError> {
+        let mut unreadable = Vec::new();
+        let mut items = match & self.options.files_from {
+            Some(source) => read_file_list(source)?,
+            None if self.options.fail_fast => self.find_files(None)?,
+            None => self.find_files(Some(& mut unreadable))?
+        };
+        let own = self.own_files();
+        if ! own.is_empty() {
+            // Only items with the name of an output are worth resolving
+            let names: HashSet<& OsStr> = own.iter()
+                .filter_map(|path| path.file_name())
+                .collect();
+            items.retain(|item| {
+                let is_own = item.file_name().is_some_and(|n| names.contains(n))
+                    && canonicalize(item).is_ok_and(|path| own.contains(& path));
+                if is_own {
+                    eprintln!(
+                        "Warning: not archiving {}, which is part of the \
+                        archive being written", item.display()
+                    );
+                }
+                ! is_own
+            });
+        }
+        let mut excluded_files = 0;
+        let mut excluded_bytes = 0;
+        if self.options.exclude_larger_than.is_some()
+                || self.options.exclude_smaller_than.is_some() {
+            items.retain(|item| match symlink_metadata(item) {
+                Ok(meta) if meta.is_file() && self.excluded_size(meta.len()) => {
+                    excluded_files += 1;
+                    excluded_bytes += meta.len();
+                    false
+                }
+                _ => true
+            });
+        }
+        if self.options.deterministic {
+            items.sort();
+        }
+        Ok(WorkList { items, unreadable, excluded_files, excluded_bytes })
+    }
+
+    // Archive the target (or the `files_from` list) into
+    // `options.num_threads` shards. An `archive_name` of "-" writes a single
+    // archive to stdout instead (one worker, no parts or checksums).
+    pub fn create(& self) -> Result<CreateReport, ArchiverError> {
+        let started = Instant::now();
+        let opts = & self.options;
+        self.check_threads()?;
+        self.check_shard_index()?;
+        // A target outside of `relative_to` is refused before the walk
+        opts.relative_roots()?;
+        let distribution = if opts.group_dirs {
+            Distribution::ParentHash
+        } else if opts.deterministic || opts.stable_placement {
+            Distribution::PathHash
+        } else {
+            Distribution::Shared
+        };
+
+        if opts.files_from.is_none() {
+            status!(
+                self.archive_name,
+                "Enumerating files. Following links? {}", opts.follow_links
+            );
+        }
+        let list = self.enumerate()?;
+        if list.excluded_files > 0 {
+            status!(
+                self.archive_name, "Excluded {} files ({}) by size",
+                format_count(list.excluded_files),
+                format_size(list.excluded_bytes)
+            );
+        }
+        let mut work_items = list.items;
+        let dir_targets = opts.dir_targets();
+        if opts.strip_root && ! dir_targets.is_empty() {
+            status!(
+                self.archive_name,
+                "Storing the contents of {} at the top level of the archive",
+                dir_targets.join(", ")
+            );
+        } else if let Some(base) = & opts.relative_to {
+            status!(
+                self.archive_name, "Storing member names relative to {}", base
+            );
+        } else if work_items.iter().any(|item| item.is_absolute()) {
+            if opts.absolute_names {
+                status!(self.archive_name, "Storing absolute member names");
+            } else {
+                status!(
+                    self.archive_name, "Removing leading '/' from member names"
+                );
+            }
+        }
+        if opts.check_case {
+            let collisions = case_collisions(& work_items);
+            for (first, item) in collisions.iter().take(MAX_COLLISIONS_LISTED) {
+                eprintln!(
+                    "Warning: {} and {} differ only in case",
+                    first.display(), item.display()
+                );
+            }
+            let unlisted =
+                collisions.len().saturating_sub(MAX_COLLISIONS_LISTED);
+            if unlisted > 0 {
+                eprintln!(
+                    "Warning: ... and {} more paths that differ only in case",
+                    format_count(unlisted as u64)
+                );
+            }
+        }
+        let mut references = References::default();
+        if opts.dedup {
+            (work_items, references) = find_duplicates(
+                work_items, opts.max_file_size, header_mode(opts.deterministic)
+            );
+        }
+
+        // A stream has to come from a single worker to stay one valid
+        // archive
+        let num_shards = if self.archive_name == STDOUT {
+            1
+        } else {
+            opts.num_threads
+        };
+        let shards: Vec<u32> = (0..num_shards).collect();
+        let mut journal = match & opts.journal {
+            Some(path) => Some(Journal::open(path)?),
+            None => None
+        };
+        if let Some(journal) = journal.as_ref().filter(|j| j.resumed) {
+            status!(
+                self.archive_name, "Resuming: {} files were already archived",
+                format_count(journal.archived.len() as u64)
+            );
+            work_items.retain(
+                |item| ! journal.contains(item.as_os_str().as_encoded_bytes())
+            );
+        }
+        if let Some(margin) = opts.check_space {
+            if self.archive_name != STDOUT {
+                self.check_space(& work_items, & shards, margin)?;
+            }
+        }
+        let mut report = run_workers(
+            & self.archive_name, & shards, & work_items, distribution, opts,
+            journal.as_mut()
+        )?;
+        report.unreadable = list.unreadable;
+        if opts.dedup {
+            self.save_references(references, & mut report)?;
+        } else if ! opts.append {
+            // Left over from an earlier deduplicating run
+            let _ = std::fs::remove_file(dedup_path(& self.archive_name));
+        }
+
+        if opts.checksums {
+            write_checksums(
+                & format!("{}.SHA256SUMS", self.archive_name), & report.checksums
+            )?;
+        }
+        // Kept while there is something left to do: the rest of an
+        // interrupted run, or the items that failed
+        if let Some(journal) = journal {
+            if ! report.interrupted && report.failed.is_empty() {
+                journal.remove()?;
+            }
+        }
+        report.elapsed = started.elapsed();
+        self.check_failed(report)
+    }
+
+    // Archive only the files that `plan_path` assigns to `shard`, writing
+    // `{archive_name}.{shard}.tar` and a manifest fragment listing what was
+    // archived. Paths in the plan are used as-is, so this must run from the
+    // same working directory the plan was computed in. `options.target` and
+    // `options.num_threads` are ignored: both come from the plan.
+    pub fn create_from_plan(
+            & self, plan_path: & str, shard: u32
+        ) -> Result<CreateReport, ArchiverError> {
+        let started = Instant::now();
+        let plan = Plan::load(plan_path).map_err(ArchiverError::Plan)?;
+        let num_shards = plan.shards.len() as u32;
+        if shard >= num_shards {
+            return Err(ArchiverError::InvalidArg(format!(
+                "shard {} requested, but plan {} only has {} shards",
+                shard, plan_path, num_shards
+            )));
+        }
+
+        println!(
+            "Archiving shard {} of {} from plan for {}",
+            shard, num_shards, plan.target
+        );
+        let work_items: Vec<PathBuf> = plan.shards[shard as usize].iter()
+            .map(|f| PathBuf::from(& f.path))
+            .collect();
+        let mut report = run_workers(
+            & self.archive_name, & [shard], & work_items, Distribution::Shared,
+            & self.options, None
+        )?;
+
+        if self.options.checksums {
+            write_checksums(
+                & format!("{}.{}.SHA256SUMS", self.archive_name, shard),
+                & report.checksums
+            )?;
+        }
+        let fragment = Fragment {
+            shard,
+            num_shards,
+            planned: work_items.len() as u64,
+            files: report.processed.iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect()
+        };
+        fragment.save(& fragment_path(& self.archive_name, shard))?;
+        report.elapsed = started.elapsed();
+        self.check_failed(report)
+    }
+
+    // Unpack `options.num_threads` shards into the target, one thread per
+    // shard. The report counts entries that met an existing file.
+    // Shards 0..num_threads are expected (a missing one fails), and any
+    // further shards found on disk are read too, so that -n need not match
+    // the number of shards
+    pub fn num_shards(& self) -> u32 {
+        let found = self.options.shard_names().count(& self.archive_name);
+        found.max(self.options.num_threads)
+    }
+
+    // Run `work` on every shard (see num_shards) with `num_threads` workers
+    // that take shard indices from a shared queue, so that any number of
+    // workers serves any number of shards, and a worker done with a small
+    // shard moves on to the next. Results are in shard order; the first
+    // shard (by index) that failed fails the whole.
+    fn for_each_shard<T, F>(& self, work: F) -> Result<Vec<T>, ArchiverError>
+        where T: Send + 'static,
+              F: Fn(u32) -> Result<T, ArchiverError> + Send + Sync + 'static {
+        self.check_threads()?;
+        let num_shards = self.num_shards();
+        let (tx, rx) = channel();
+        for idx in 0..num_shards {
+            tx.send(idx).map_err(|e| ArchiverError::Worker(e.to_string()))?;
+        }
+        drop(tx);
+        let rx = Arc::new(Mutex::new(rx));
+        let work = Arc::new(work);
+
+        let mut handles = Vec::new();
+        for _ in 0..self.options.num_threads.min(num_shards) {
+            let rx = Arc::clone(& rx);
+            let work = Arc::clone(& work);
+            handles.push(thread::spawn(move || {
+                let mut results = Vec::new();
+                // The queue is full from the start: empty means done
+                while let Ok(idx) = rx.lock().unwrap().try_recv() {
+                    results.push((idx, work(idx)));
+                }
+                Ok(results)
+            }));
+        }
+        let mut results: Vec<(u32, Result<T, ArchiverError>)> =
+            join_workers(handles)?.into_iter().flatten().collect();
+        results.sort_by_key(|(idx, _)| * idx);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    // With a journal, every entry written is recorded in it, and a journal
+    // left by an interrupted extract has its entries skipped (if they are
+    // still there; the others meet the conflict policy as usual, so with
+    // the default a file cut short is written again). It is removed once
+    // every shard is extracted.
+    pub fn extract(& self) -> Result<ExtractReport, ArchiverError> {
+        // Before the journal or the destination are touched
+        self.check_threads()?;
+        let started = Instant::now();
+        let mut config = self.options.extract_config();
+        if let Some(path) = & self.options.journal {
+            let journal = Journal::open(path)?;
+            if journal.resumed {
+                println!(
+                    "Resuming: {} entries were already extracted",
+                    format_count(journal.archived.len() as u64)
+                );
+            }
+            config.journal = Some(Arc::new(Mutex::new(journal)));
+        }
+        // Created up front: Entry::unpack_in refuses a destination that does
+        // not exist yet, so the first shard to create it would race the
+        // others
+        create_dir_all(& self.options.target)?;
+
+        println!(
+            "Starting {} worker threads for {} shards",
+            self.options.num_threads, self.num_shards()
+        );
+        let name = self.archive_name.clone();
+        let names = self.options.shard_names();
+        let ctarget = self.options.target.clone();
+        let shard_config = config.clone();
+        let extracted = self.for_each_shard(move |idx| {
+            extract_shard(
+                name.as_str(), & names, idx, ctarget.as_str(),
+                shard_config.clone()
+            )
+        });
+
+        println!(" ... waiting for workers to finish ...");
+        let mut report = ExtractReport::default();
+        let mut deferred = Vec::new();
+        for (shard_report, shard_links) in extracted? {
+            report.add(& shard_report);
+            deferred.extend(shard_links);
+        }
+        // Hard links to files other shards hold
+        link_deferred(& deferred, & config)?;
+        // Duplicates can only be recreated once their originals are unpacked
+        let references = dedup_path(& self.archive_name);
+        if Path::new(& references).exists() {
+            let references = References::load(& references)
+                .map_err(ArchiverError::Plan)?;
+            report.add(& references.extract(
+                & self.options.target, & config
+            )?);
+        }
+        if let Some(journal) = config.journal.take() {
+            if let Ok(journal) = Arc::try_unwrap(journal) {
+                journal.into_inner().unwrap().remove()?;
+            }
+        }
+        println!(" ... workers are done.");
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    // Read every shard through, as extract would, but write nothing; returns
+    // the number of entries. Fails with the first shard (by index) that is
+    // not valid Tar (ArchiverError::Corrupt).
+    pub fn test(& self) -> Result<u64, ArchiverError> {
+        let name = self.archive_name.clone();
+        let names = self.options.shard_names();
+        let entries = self.for_each_shard(
+            move |idx| test_shard(name.as_str(), & names, idx)
+        )?;
+        Ok(entries.iter().sum())
+    }
+
+    // Write the data of the one entry archived as `member` to `out`, like
+    // `tar -xO`, and return the number of bytes. Every shard is searched;
+    // no entry by that name, or more than one, is InvalidArg.
+    pub fn extract_member<W: Write>(
+            & self, member: & str, out: & mut W
+        ) -> Result<u64, ArchiverError> {
+        let wanted = strip_path(Path::new(member), 0).ok_or_else(|| {
+            ArchiverError::InvalidArg(
+                format!("{} is not an entry name", member)
+            )
+        })?;
+        let name = self.archive_name.clone();
+        let names = self.options.shard_names();
+        let cwanted = wanted.clone();
+        let found: Vec<String> = self.for_each_shard(
+            move |idx| find_member(name.as_str(), & names, idx, & cwanted)
+        )?.into_iter().flatten().collect();
+        match found.as_slice() {
+            [tar_path] => copy_member(tar_path, & wanted, out)
+                .map_err(|e| match e {
+                    ArchiverError::InvalidArg(_) => e,
+                    e => e.in_shard(tar_path)
+                }),
+            [] => Err(ArchiverError::InvalidArg(format!(
+                "no entry {} in {}", member, self.archive_name
+            ))),
+            _ => Err(ArchiverError::InvalidArg(format!(
+                "{} matches {} entries (in {})",
+                member, found.len(), found.join(", ")
+            )))
+        }
+    }
+
+    // What extract would do with every entry, shard by shard, without
+    // writing anything (references of a deduplicated archive are not
+    // included)
+    pub fn preview_extract(& self) -> Result<Vec<Preview>, ArchiverError> {
+        let config = self.options.extract_config();
+        let name = self.archive_name.clone();
+        let names = self.options.shard_names();
+        let ctarget = self.options.target.clone();
+        let previews = self.for_each_shard(move |idx| {
+            preview_shard(
+                name.as_str(), & names, idx, ctarget.as_str(), config.clone()
+            )
+        })?;
+        Ok(previews.into_iter().flatten().collect())
+    }
+
+    // Compute the shard assignment create would use in deterministic mode,
+    // so that N independent processes can each archive one shard
+    pub fn plan(& self) -> Result<Plan, ArchiverError> {
+        self.check_threads()?;
+        let distribution = if self.options.group_dirs {
+            Distribution::ParentHash
+        } else {
+            Distribution::PathHash
+        };
+
+        let mut work_items = self.find_files(None)?;
+        work_items.sort();
+        Ok(Plan::build(
+            & self.options.target, & work_items, self.options.num_threads,
+            distribution
+        ))
+    }
+
+    // SHA-256 over the SHA-256 of every regular file under the target, in
+    // sorted order. Unlike the layout of the shards, this depends on neither
+    // file names, directory structure nor metadata, so two trees that hold
+    // the same files in different directories have the same digest. Every
+    // file is hashed by itself, so where one file ends and the next begins
+    // counts too.
+    pub fn content_digest(& self) -> Result<String, ArchiverError> {
+        let mut files: Vec<PathBuf> = Vec::new();
+        for path in self.find_files(None)? {
+            let meta = if self.options.follow_links {
+                metadata(& path)
+            } else {
+                symlink_metadata(& path)
+            };
+            let meta = match meta {
+                Ok(meta) => meta,
+                // Removed since the walk listed it
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into())
+            };
+            if meta.is_file() {
+                files.push(path);
+            }
+        }
+        let mut digests = Vec::with_capacity(files.len());
+        for path in & files {
+            let mut hasher = Sha256::new();
+            io::copy(& mut File::open(path)?, & mut hasher)?;
+            digests.push(hasher.finish());
+        }
+        // The canonical order comes from the contents alone
+        digests.sort();
+
+        let mut hasher = Sha256::new();
+        for digest in & digests {
+            hasher.update(digest);
+        }
+        Ok(hasher.finish_hex())
+    }
+}
@@ -0,0 +1,107 @@
+// Synthetic trees for `create --benchmark`: measure archiving throughput
+// for a thread count on this machine without a real data set. The tree and
+// the shards live in one scratch directory that is removed afterwards.
+use std::fs::{File, create_dir, create_dir_all, remove_dir_all};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+
+// Files per directory of the synthetic tree
+const FILES_PER_DIR: u64 = 1000;
+
+
+// xorshift64: reproducible sizes and contents, no dependency
+struct Random(u64);
+
+
+impl Random {
+    fn next(& mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Uniform in (0, 1]
+    fn unit(& mut self) -> f64 {
+        ((self.next() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+
+pub struct Scratch {
+    dir: PathBuf,
+    // Regular files generated, and their total size
+    pub files: u64,
+    pub bytes: u64
+}
+
+
+impl Scratch {
+    // Generate `count` files in a new scratch directory below `parent`.
+    // Sizes follow an exponential distribution with mean `mean_size` (many
+    // small files, few large ones, as in most real trees); the same
+    // arguments always give the same tree.
+    pub fn generate(
+            parent: & Path, count: u64, mean_size: u64
+        ) -> io::Result<Scratch> {
+        let dir = parent.join(
+            format!("parallel-tar-benchmark.{}", process::id())
+        );
+        create_dir_all(parent)?;
+        create_dir(& dir)?;
+        let mut scratch = Scratch { dir, files: 0, bytes: 0 };
+        if let Err(e) = scratch.fill(count, mean_size) {
+            scratch.remove();
+            return Err(e);
+        }
+        Ok(scratch)
+    }
+
+    fn fill(& mut self, count: u64, mean_size: u64) -> io::Result<()> {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        let mut block = vec![0u8; 1 << 16];
+        for chunk in block.chunks_mut(8) {
+            let bytes = random.next().to_le_bytes();
+            chunk.copy_from_slice(& bytes[.. chunk.len()]);
+        }
+        for idx in 0..count {
+            let dir = self.tree().join(format!("d{}", idx / FILES_PER_DIR));
+            if idx % FILES_PER_DIR == 0 {
+                create_dir_all(& dir)?;
+            }
+            let size = (-(mean_size as f64) * random.unit().ln()) as u64;
+            let mut file = File::create(dir.join(format!("f{}", idx)))?;
+            let mut left = size;
+            while left > 0 {
+                let n = left.min(block.len() as u64) as usize;
+                file.write_all(& block[.. n])?;
+                left -= n as u64;
+            }
+            self.files += 1;
+            self.bytes += size;
+        }
+        Ok(())
+    }
+
+    // The directory to archive
+    pub fn tree(& self) -> PathBuf {
+        self.dir.join("tree")
+    }
+
+    // The archive name to create the shards under
+    pub fn archive_name(& self) -> PathBuf {
+        self.dir.join("archive")
+    }
+
+    // Delete the tree and the shards (failure is only reported: it does not
+    // change the measurement)
+    pub fn remove(& self) {
+        if let Err(e) = remove_dir_all(& self.dir) {
+            eprintln!(
+                "Warning: cannot remove {}: {}", self.dir.display(), e
+            );
+        }
+    }
+}
@@ -0,0 +1,1190 @@
+// Command line parsing. Argument definitions live here so that every mode
+// (and any future binary) shares the same flag names and conventions.
+use std::time::Duration;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use clap::error::ErrorKind;
+
+use parallel_tar::Timing;
+use parallel_tar::extract::ConflictPolicy;
+use parallel_tar::format::Format;
+use parallel_tar::shard::{NameTemplate, STDOUT, ShardLimits};
+use parallel_tar::size::{parse_duration, parse_size};
+use parallel_tar::transform::PrefixRewrite;
+
+
+pub enum Mode {
+    Create,
+    Extract,
+    Plan,
+    MergeManifests,
+    ContentDigest
+}
+
+
+pub struct Options {
+    pub mode: Mode,
+    // Fragments to merge in MergeManifests mode, otherwise a single target
+    pub targets: Vec<String>,
+    pub archive_name: String,
+    pub num_threads: u32,
+    pub follow_links: bool,
+    pub follow_toplevel: bool,
+    pub one_file_system: bool,
+    pub check_case: bool,
+    pub excludes: Vec<String>,
+    pub exclude_vcs: bool,
+    pub exclude_larger_than: Option<u64>,
+    pub max_file_size: Option<u64>,
+    pub exclude_smaller_than: Option<u64>,
+    pub deterministic: bool,
+    pub format: Format,
+    pub group_dirs: bool,
+    pub stable_placement: bool,
+    pub output: Option<String>,
+    pub limits: ShardLimits,
+    pub checksums: bool,
+    pub append: bool,
+    pub ignore_errors: bool,
+    pub fail_fast: bool,
+    pub skip_special: bool,
+    pub dedup: bool,
+    pub journal: Option<String>,
+    pub preserve_hardlinks: bool,
+    pub fsync: bool,
+    pub shard_index: bool,
+    pub max_runtime: Option<Duration>,
+    pub absolute_names: bool,
+    pub xattrs: bool,
+    pub sparse: bool,
+    pub check_symlinks: bool,
+    pub name_template: NameTemplate,
+    pub shard_dirs: Vec<String>,
+    pub transform: Option<PrefixRewrite>,
+    pub strip_root: bool,
+    pub relative_to: Option<String>,
+    pub check_space: Option<u32>,
+    pub timing: Timing,
+    pub quiet: bool,
+    pub progress_fd: Option<i32>,
+    pub progress_file: Option<String>,
+    pub verbose: bool,
+    pub list_threads: bool,
+    pub dry_run: bool,
+    pub test: bool,
+    pub benchmark: Option<u64>,
+    pub benchmark_size: u64,
+    pub json_errors: bool,
+    pub to_stdout: bool,
+    pub only: Option<String>,
+    pub files_from: Option<String>,
+    pub on_conflict: ConflictPolicy,
+    pub strip_components: usize,
+    pub subdir: Option<String>,
+    pub flatten: bool,
+    pub regular_only: bool,
+    pub dereference_hardlinks: bool,
+    pub no_same_permissions: bool,
+    pub parallel_entries: u32,
+    pub from_plan: Option<String>,
+    pub verify: Option<String>,
+    pub regenerate: bool,
+    pub shard: Option<u32>
+}
+
+
+pub fn get_arg<T: Clone + Send + Sync + 'static>(
+        args: & ArgMatches, name: & str
+    ) -> T {
+    args.get_one::<T>(name).unwrap().clone()
+}
+
+
+pub fn get_opt<T: Clone + Send + Sync + 'static>(
+        args: & ArgMatches, name: & str
+    ) -> Option<T> {
+    args.get_one::<T>(name).cloned()
+}
+
+
+pub fn get_flag(args: & ArgMatches, name: & str) -> bool {
+    args.get_flag(name)
+}
+
+
+pub fn get_list(args: & ArgMatches, name: & str) -> Vec<String> {
+    args.get_many::<String>(name).unwrap_or_default().cloned().collect()
+}
+
+
+pub fn target_arg() -> Arg {
+    Arg::new("target")
+    .value_name("TARGET")
+    .help(
+        "Target for compression/decompression (--create accepts several, \
+        each archived under its own name; the fragments to combine with \
+        --merge-manifests)"
+    )
+    .required_unless_present_any([
+        "from_plan", "verify", "files_from", "test", "to_stdout",
+        "benchmark"
+    ])
+    .num_args(1..)
+    .index(1)
+}
+
+
+pub fn archive_name_arg() -> Arg {
+    Arg::new("archive_name")
+    .short('f')
+    .long("file")
+    .help(
+        "Name of the Tar archive ('-' writes one stream to stdout; -n is \
+        then ignored). A shard path that is an existing named pipe is \
+        written as one stream, once a reader has opened it"
+    )
+    .required_unless_present_any(
+        ["plan", "merge_manifests", "content_digest", "benchmark"]
+    )
+    .num_args(1)
+}
+
+
+pub fn num_threads_arg() -> Arg {
+    Arg::new("num_threads")
+    .short('n')
+    .help(
+        "Number of parallel threads to use (create writes one shard per \
+        thread; extract reads shards 0 to N-1 and any further ones it finds)"
+    )
+    .required_unless_present_any(
+        ["from_plan", "merge_manifests", "verify", "content_digest"]
+    )
+    .num_args(1)
+    .value_parser(clap::value_parser!(u32).range(1..))
+}
+
+
+pub fn follow_links_arg() -> Arg {
+    Arg::new("follow_links")
+    .short('l')
+    .long("follow")
+    .help("Follow links while enumerating files")
+    .required(false)
+    .action(ArgAction::SetTrue)
+}
+
+
+pub fn exclude_arg() -> Arg {
+    Arg::new("exclude")
+    .long("exclude")
+    .value_name("GLOB")
+    .help("Skip paths matching GLOB (supports *, ** and ?; repeatable)")
+    .required(false)
+    .num_args(1)
+    .action(ArgAction::Append)
+}
+
+
+pub fn command() -> Command {
+    Command::new("Parallel Tar")
+        .version("1.0")
+        .author("Johannes Blaschke")
+        .about("Add target directory to parallel list of Tar archives.")
+        .arg(target_arg())
+        .arg(
+            Arg::new("create")
+            .short('c')
+            .long("create")
+            .help("Create an archive")
+            .required_unless_present_any(
+                ["extract", "plan", "merge_manifests", "content_digest"]
+            )
+            .conflicts_with_all(
+                ["extract", "plan", "merge_manifests", "content_digest"]
+            )
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("extract")
+            .short('x')
+            .long("extract")
+            .help("Extract a list of archives")
+            .required_unless_present_any(
+                ["create", "plan", "merge_manifests", "content_digest"]
+            )
+            .conflicts_with_all(
+                ["create", "plan", "merge_manifests", "content_digest"]
+            )
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("plan")
+            .short('p')
+            .long("plan")
+            .help(
+                "Print the deterministic shard assignment of every file as \
+                JSON, without archiving"
+            )
+            .required_unless_present_any(
+                ["create", "extract", "merge_manifests", "content_digest"]
+            )
+            .conflicts_with_all(
+                ["create", "extract", "merge_manifests", "content_digest"]
+            )
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("merge_manifests")
+            .long("merge-manifests")
+            .help(
+                "Combine the manifest fragments given as TARGETs (from \
+                --from-plan runs) into one manifest"
+            )
+            .required_unless_present_any(
+                ["create", "extract", "plan", "content_digest"]
+            )
+            .conflicts_with_all(
+                ["create", "extract", "plan", "content_digest"]
+            )
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("content_digest")
+            .long("content-digest")
+            .help(
+                "Print a SHA-256 of the contents of all files under TARGET, \
+                independent of their names and the directories they are in"
+            )
+            .required_unless_present_any(
+                ["create", "extract", "plan", "merge_manifests"]
+            )
+            .conflicts_with_all(
+                ["create", "extract", "plan", "merge_manifests"]
+            )
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_name("PATH")
+            .help(
+                "Write the plan or merged manifest to PATH instead of stdout \
+                (with --regenerate: where to write the new plan)"
+            )
+            .required(false)
+            .num_args(1)
+        )
+        .arg(
+            Arg::new("verify")
+            .long("verify")
+            .value_name("PLAN")
+            .help(
+                "With --plan: re-stat the files in PLAN and report any that \
+                are missing or changed size (exit status 1 if any are)"
+            )
+            .required(false)
+            .num_args(1)
+            .requires("plan")
+            .conflicts_with("target")
+        )
+        .arg(
+            Arg::new("regenerate")
+            .long("regenerate")
+            .help(
+                "With --verify: if PLAN is stale, recompute it for the same \
+                target, shard count and placement and write it to --output \
+                (or over PLAN)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("verify")
+        )
+        .arg(follow_links_arg())
+        .arg(
+            Arg::new("follow_toplevel")
+            .long("follow-toplevel")
+            .help(
+                "Follow TARGETs that are symlinks (archiving what they point \
+                to under the TARGET name), but store symlinks below them as \
+                links"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+            .conflicts_with("follow_links")
+        )
+        .arg(
+            Arg::new("one_file_system")
+            .long("one-file-system")
+            .help(
+                "Do not descend into directories on other file systems \
+                (mount points are archived, their contents are not)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("check_case")
+            .long("check-case")
+            .help(
+                "Warn about paths that differ only in case (Foo.txt and \
+                foo.txt), which overwrite each other when extracted onto a \
+                case-insensitive file system"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(archive_name_arg())
+        .arg(num_threads_arg())
+        .arg(exclude_arg())
+        .arg(
+            Arg::new("exclude_vcs")
+            .long("exclude-vcs")
+            .help(
+                "Skip version control directories and files (.git, .svn, \
+                .hg, CVS, .gitignore and the like), in addition to any \
+                --exclude"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("exclude_larger_than")
+            .long("exclude-larger-than")
+            .value_name("SIZE")
+            .help("Skip regular files larger than SIZE (e.g. 10G)")
+            .required(false)
+            .num_args(1)
+            .value_parser(parse_size)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("exclude_smaller_than")
+            .long("exclude-smaller-than")
+            .value_name("SIZE")
+            .help("Skip regular files smaller than SIZE (e.g. 1M)")
+            .required(false)
+            .num_args(1)
+            .value_parser(parse_size)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("max_file_size")
+            .long("max-file-size")
+            .value_name("SIZE")
+            .help(
+                "Leave out, with a warning, regular files that are larger \
+                than SIZE when they are read (a safety limit, unlike \
+                --exclude-larger-than it catches files that grew)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(parse_size)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("deterministic")
+            .long("deterministic")
+            .help(
+                "Produce byte-identical shards across runs (sorted, \
+                path-hashed placement; drops mtimes, ownership and most \
+                permission bits)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help(
+                "Header format for create: 'gnu' (long names in GNU \
+                entries; GNU tar, bsdtar), 'pax' (long names in PAX headers; \
+                any POSIX tar) or 'ustar' (names up to 255 bytes only, no \
+                extensions; every tar). --sparse needs gnu, --xattrs and \
+                --shard-index gnu or pax"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(["gnu", "pax", "ustar"])
+            .default_value("gnu")
+        )
+        .arg(
+            Arg::new("locality")
+            .long("locality")
+            .value_name("UNIT")
+            .help(
+                "Shard placement: 'file' balances individual files, 'dir' \
+                keeps files of the same directory in the same shard"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(["file", "dir"])
+            .default_value("file")
+        )
+        .arg(
+            Arg::new("stable_placement")
+            .long("stable-placement")
+            .help(
+                "Place every file by a stable hash of its path, so it lands \
+                in the same shard on every run (shards are then balanced by \
+                path only, not by size)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("max_members")
+            .long("max-members-per-shard")
+            .value_name("N")
+            .help(
+                "Start a new shard part ({name}.{idx}.{part}.tar) once a \
+                shard holds N entries"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(u64).range(1..))
+        )
+        .arg(
+            Arg::new("max_shard_bytes")
+            .long("max-shard-bytes")
+            .value_name("SIZE")
+            .help(
+                "Start a new shard part once the current one has grown past \
+                SIZE (e.g. 512M, 1G)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(parse_size)
+        )
+        .arg(
+            Arg::new("append")
+            .long("append")
+            .help(
+                "Add the files to the existing shards of the archive \
+                instead of replacing them (use the same -n and placement \
+                as before)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+            .conflicts_with("from_plan")
+        )
+        .arg(
+            Arg::new("ignore_errors")
+            .long("ignore-errors")
+            .help(
+                "Warn about files that cannot be archived instead of \
+                exiting with an error"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("fail_fast")
+            .long("fail-fast")
+            .help(
+                "Stop before archiving anything if a directory under TARGET \
+                cannot be read (by default it is left out with a warning)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("skip_special")
+            .long("skip-special")
+            .help(
+                "Leave FIFOs and device nodes out of the archive (with a \
+                warning) instead of archiving them"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("absolute_names")
+            .short('P')
+            .long("absolute-names")
+            .help(
+                "Store absolute paths with their leading '/' (by default it \
+                is removed, as tar does)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("xattrs")
+            .long("xattrs")
+            .help(
+                "Archive extended attributes (e.g. SELinux labels) when \
+                creating, and restore them when extracting"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("name_template")
+            .long("name-template")
+            .value_name("TEMPLATE")
+            .help(
+                "Names of the shard files, from {name}, {index}, {index:0N} \
+                (zero-padded to N digits) and {ext}; extract needs the same \
+                template as create"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(NameTemplate::parse)
+            .default_value(NameTemplate::DEFAULT)
+        )
+        .arg(
+            Arg::new("strip_root")
+            .long("strip-root")
+            .help(
+                "Store the contents of a directory TARGET at the top of the \
+                archive (foo.txt instead of TARGET/foo.txt); the contents of \
+                several TARGETs are merged"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("check_space")
+            .long("check-space")
+            .value_name("PERCENT")
+            .help(
+                "Before archiving, check that the file systems the shards go \
+                to have room for them (estimated from the file sizes), plus \
+                an optional safety margin of PERCENT"
+            )
+            .required(false)
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("0")
+            .value_parser(clap::value_parser!(u32))
+            .requires("create")
+        )
+        .arg(
+            Arg::new("relative_to")
+            .long("relative-to")
+            .value_name("BASE")
+            .help(
+                "Store every TARGET under its path relative to the directory \
+                BASE (e.g. /data/project/sub as project/sub with \
+                --relative-to /data), wherever this is run from; every \
+                TARGET has to be below BASE"
+            )
+            .required(false)
+            .num_args(1)
+            .requires("create")
+            .conflicts_with_all(["strip_root", "files_from", "from_plan"])
+        )
+        .arg(
+            Arg::new("transform")
+            .long("transform")
+            .value_name("EXPR")
+            .help(
+                "Archive paths starting with OLD under NEW instead, given as \
+                s/OLD/NEW/ (whole path components; an empty NEW drops OLD \
+                itself)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(PrefixRewrite::parse)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("shard_dir")
+            .long("shard-dir")
+            .value_name("DIR")
+            .help(
+                "Put shard N into the Nth DIR, round-robin (repeatable; e.g. \
+                one per disk). Extract needs the same list, in the same order"
+            )
+            .required(false)
+            .num_args(1)
+            .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("check_symlinks")
+            .long("check-symlinks")
+            .help(
+                "Treat symlinks whose target does not exist as files that \
+                cannot be archived (fails create unless --ignore-errors)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("sparse")
+            .short('S')
+            .long("sparse")
+            .help(
+                "Store only the data of files with holes, which extract \
+                recreates (Linux)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("dedup")
+            .long("dedup")
+            .help(
+                "Archive files with identical contents only once and record \
+                the other paths with their metadata in {name}.dedup.json \
+                (extract copies them back)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+            .conflicts_with_all(["append", "from_plan"])
+        )
+        .arg(
+            Arg::new("journal")
+            .long("journal")
+            .value_name("PATH")
+            .help(
+                "Record every archived (or extracted) file in PATH; if PATH \
+                is left over from a run that died or was interrupted, skip \
+                the files it lists and continue its shards (PATH is removed \
+                once everything is done). Extract skips only listed files \
+                that still exist"
+            )
+            .required(false)
+            .num_args(1)
+            .conflicts_with_all(["dedup", "from_plan"])
+        )
+        .arg(
+            Arg::new("preserve_hardlinks")
+            .long("preserve-hardlinks")
+            .help(
+                "Archive further names of an already archived file as hard \
+                links to it instead of storing the file again (extract \
+                recreates the links, across shards too)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("fsync")
+            .long("fsync")
+            .help(
+                "Flush every shard file to disk once it is finished, so that \
+                the archive survives a crash right after create (slower, \
+                especially with many parts)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("max_runtime")
+            .long("max-runtime")
+            .value_name("DURATION")
+            .help(
+                "Stop once DURATION (e.g. 90s, 30m, 2h) has passed, as a \
+                first Ctrl-C would: files being archived are completed, no \
+                new ones are started, and the shards stay valid. Exits with \
+                124 if the limit was hit (keep the --journal to continue)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(parse_duration)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("shard_index")
+            .long("shard-index")
+            .help(
+                "End every shard file with a .ptar-shard-index.json entry \
+                listing the entries before it, so that a shard describes \
+                itself (extract leaves the index entry out)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+            .conflicts_with_all(["append", "journal"])
+        )
+        .arg(
+            Arg::new("checksums")
+            .long("checksums")
+            .help(
+                "Write {name}.SHA256SUMS for the produced shards (check with \
+                sha256sum -c)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("files_from")
+            .long("files-from")
+            .value_name("LIST")
+            .help(
+                "Archive the newline-separated paths in LIST ('-' for stdin) \
+                instead of walking a TARGET"
+            )
+            .required(false)
+            .num_args(1)
+            .requires("create")
+            .conflicts_with_all(["target", "from_plan"])
+        )
+        .arg(
+            Arg::new("on_conflict")
+            .long("on-conflict")
+            .value_name("POLICY")
+            .help(
+                "When extracting over an existing file: overwrite it, skip \
+                the entry, keep the newer of the two, or stop with an error"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(["overwrite", "skip", "keep-newer", "error"])
+            .default_value("overwrite")
+        )
+        .arg(
+            Arg::new("strip_components")
+            .long("strip-components")
+            .value_name("N")
+            .help(
+                "Drop the first N path components of every entry when \
+                extracting (entries with no more than N are skipped)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(usize))
+            .default_value("0")
+        )
+        .arg(
+            Arg::new("subdir")
+            .long("subdir")
+            .value_name("PATH")
+            .help(
+                "Extract only the entries at or below PATH in the archive \
+                (with --strip-components, N counts from the top of the \
+                archived path, so use it to drop PATH's leading components)"
+            )
+            .required(false)
+            .num_args(1)
+            .requires("extract")
+        )
+        .arg(
+            Arg::new("flatten")
+            .long("flatten")
+            .help(
+                "Extract every file directly into TARGET under its file name \
+                alone, without directories (files with the same name are \
+                conflicts, see --on-conflict)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("extract")
+        )
+        .arg(
+            Arg::new("regular_only")
+            .long("regular-only")
+            .help(
+                "Extract only regular files and directories (and hard links), \
+                skipping device nodes, FIFOs and symlinks, e.g. to restore a \
+                system tree without root"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("extract")
+        )
+        .arg(
+            Arg::new("dereference_hardlinks")
+            .long("dereference-hardlinks")
+            .help(
+                "Extract every hard link as a copy of the file it links to \
+                (which may be in another shard), for file systems that do \
+                not support hard links"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("extract")
+        )
+        .arg(
+            Arg::new("no_same_permissions")
+            .long("no-same-permissions")
+            .help(
+                "Extract files and directories with their archived modes \
+                masked by the umask, instead of the archived modes as they \
+                are"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("extract")
+        )
+        .arg(
+            Arg::new("parallel_entries")
+            .long("parallel-entries")
+            .value_name("N")
+            .help(
+                "Write the files of each shard with N threads when \
+                extracting (helps with one large shard; holds up to 4 whole \
+                files per thread in memory)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .default_value("1")
+        )
+        .arg(
+            Arg::new("max_retries")
+            .long("max-retries")
+            .value_name("N")
+            .help("How often an idle worker polls for work before giving up")
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(u32))
+            .default_value("100")
+        )
+        .arg(
+            Arg::new("retry_wait")
+            .long("retry-wait")
+            .value_name("MS")
+            .help("Milliseconds an idle worker waits between polls")
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(u64))
+            .default_value("128")
+        )
+        .arg(
+            Arg::new("collect_timeout")
+            .long("collect-timeout")
+            .value_name("MS")
+            .help(
+                "Fail if no worker finishes a file for MS milliseconds \
+                (raise on slow storage)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(u64))
+            .default_value("4000")
+        )
+        .arg(
+            Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .help("Do not report progress while creating")
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("progress_fd")
+            .long("progress-fd")
+            .value_name("FD")
+            .help(
+                "Also write progress to the open file descriptor FD, as one \
+                JSON object per line ({done, total, bytes, shard_sizes, \
+                elapsed_secs}), for a monitoring process (Unix)"
+            )
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(i32).range(0..))
+            .requires("create")
+            .conflicts_with("progress_file")
+        )
+        .arg(
+            Arg::new("progress_file")
+            .long("progress-file")
+            .value_name("PATH")
+            .help(
+                "Like --progress-fd, but write to PATH (e.g. a named pipe)"
+            )
+            .required(false)
+            .num_args(1)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("dry_run")
+            .long("dry-run")
+            .help(
+                "Print the files create would archive (after excludes), one \
+                per line, or what extract would do with every entry, and \
+                exit without writing anything"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["from_plan", "test"])
+        )
+        .arg(
+            Arg::new("benchmark")
+            .long("benchmark")
+            .value_name("COUNT")
+            .help(
+                "Archive COUNT generated files in a scratch directory below \
+                TARGET (default: the temporary directory), report the \
+                throughput for -n, and remove the files and shards"
+            )
+            .hide(true)
+            .required(false)
+            .num_args(1)
+            .value_parser(clap::value_parser!(u64))
+            .requires("create")
+            .conflicts_with_all([
+                "archive_name", "files_from", "from_plan", "append", "journal",
+                "dry_run"
+            ])
+        )
+        .arg(
+            Arg::new("benchmark_size")
+            .long("benchmark-size")
+            .value_name("SIZE")
+            .help("Mean size of the files --benchmark generates")
+            .hide(true)
+            .required(false)
+            .num_args(1)
+            .value_parser(parse_size)
+            .default_value("64K")
+        )
+        .arg(
+            Arg::new("test")
+            .long("test")
+            .help(
+                "Read every entry of every shard to the end without writing \
+                anything, and report the first corrupt shard and offset"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("extract")
+        )
+        .arg(
+            Arg::new("json_errors")
+            .long("json-errors")
+            .help(
+                "Print a fatal error as one JSON object on stderr \
+                ({\"error_kind\", \"message\", \"path\"}) for scripts \
+                to parse, instead of a message"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("to_stdout")
+            .long("to-stdout")
+            .help(
+                "Write the data of the one entry given with --only to stdout \
+                instead of extracting, e.g. to pipe it into another program \
+                (fails if no entry, or more than one, has that name)"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires_all(["extract", "only"])
+            .conflicts_with_all(["dry_run", "test"])
+        )
+        .arg(
+            Arg::new("only")
+            .long("only")
+            .value_name("PATH")
+            .help("The entry to write with --to-stdout, as archived")
+            .required(false)
+            .num_args(1)
+            .requires("to_stdout")
+        )
+        .arg(
+            Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .help("List every archived file (and its shard) on stderr")
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+            .conflicts_with("quiet")
+        )
+        .arg(
+            Arg::new("list_threads")
+            .long("list-threads")
+            .help(
+                "After creating, report how many worker shards received \
+                files, and the fewest and most files any shard got"
+            )
+            .required(false)
+            .action(ArgAction::SetTrue)
+            .requires("create")
+        )
+        .arg(
+            Arg::new("from_plan")
+            .long("from-plan")
+            .value_name("PLAN")
+            .help(
+                "Create only the shard selected by --shard, archiving the \
+                files PLAN (from --plan) assigns to it"
+            )
+            .required(false)
+            .num_args(1)
+            .requires("shard")
+            .requires("create")
+        )
+        .arg(
+            Arg::new("shard")
+            .long("shard")
+            .value_name("IDX")
+            .help("Shard index to create with --from-plan")
+            .required(false)
+            .num_args(1)
+            .requires("from_plan")
+            .value_parser(clap::value_parser!(u32))
+        )
+}
+
+
+impl Options {
+    pub fn parse() -> Options {
+        let mut cmd = command();
+        let opts = Options::from_matches(& cmd.get_matches_mut());
+        if ! matches!(opts.mode, Mode::Create | Mode::MergeManifests)
+                && opts.targets.len() > 1 {
+            cmd.error(
+                ErrorKind::TooManyValues,
+                "only --create and --merge-manifests accept more than one \
+                TARGET"
+            ).exit();
+        }
+        if opts.sparse && opts.format != Format::Gnu
+                || (opts.xattrs || opts.shard_index)
+                    && opts.format == Format::Ustar {
+            cmd.error(
+                ErrorKind::ArgumentConflict,
+                "--sparse needs --format gnu, and --xattrs and --shard-index \
+                --format gnu or pax"
+            ).exit();
+        }
+        if opts.journal.is_some()
+                && ! matches!(opts.mode, Mode::Create | Mode::Extract) {
+            cmd.error(
+                ErrorKind::ArgumentConflict,
+                "--journal needs --create or --extract"
+            ).exit();
+        }
+        if opts.dry_run && ! matches!(opts.mode, Mode::Create | Mode::Extract) {
+            cmd.error(
+                ErrorKind::ArgumentConflict,
+                "--dry-run needs --create or --extract"
+            ).exit();
+        }
+        if opts.archive_name == STDOUT {
+            if ! matches!(opts.mode, Mode::Create) {
+                cmd.error(
+                    ErrorKind::ArgumentConflict,
+                    "-f - (stdout) is only supported with --create"
+                ).exit();
+            }
+            if opts.checksums || opts.append || opts.dedup
+                    || opts.journal.is_some() || opts.from_plan.is_some()
+                    || opts.limits.max_members.is_some()
+                    || opts.limits.max_bytes.is_some() {
+                cmd.error(
+                    ErrorKind::ArgumentConflict,
+                    "-f - writes a single stream and cannot be combined with \
+                    --append, --checksums, --dedup, --journal, --from-plan, \
+                    --max-members-per-shard or --max-shard-bytes"
+                ).exit();
+            }
+        }
+        opts
+    }
+
+    pub fn from_matches(args: & ArgMatches) -> Options {
+        let mode = if get_flag(args, "create") {
+            Mode::Create
+        } else if get_flag(args, "extract") {
+            Mode::Extract
+        } else if get_flag(args, "plan") {
+            Mode::Plan
+        } else if get_flag(args, "merge_manifests") {
+            Mode::MergeManifests
+        } else {
+            Mode::ContentDigest
+        };
+
+        Options {
+            mode,
+            targets: get_list(args, "target"),
+            archive_name: get_opt(args, "archive_name").unwrap_or_default(),
+            num_threads: get_opt(args, "num_threads").unwrap_or_default(),
+            follow_links: get_flag(args, "follow_links"),
+            follow_toplevel: get_flag(args, "follow_toplevel"),
+            one_file_system: get_flag(args, "one_file_system"),
+            check_case: get_flag(args, "check_case"),
+            excludes: get_list(args, "exclude"),
+            exclude_vcs: get_flag(args, "exclude_vcs"),
+            exclude_larger_than: get_opt(args, "exclude_larger_than"),
+            max_file_size: get_opt(args, "max_file_size"),
+            exclude_smaller_than: get_opt(args, "exclude_smaller_than"),
+            deterministic: get_flag(args, "deterministic"),
+            format: Format::from_name(& get_arg::<String>(args, "format"))
+                .unwrap(),
+            group_dirs: get_arg::<String>(args, "locality") == "dir",
+            stable_placement: get_flag(args, "stable_placement"),
+            output: get_opt(args, "output"),
+            limits: ShardLimits {
+                max_members: get_opt(args, "max_members"),
+                max_bytes: get_opt(args, "max_shard_bytes")
+            },
+            checksums: get_flag(args, "checksums"),
+            append: get_flag(args, "append"),
+            ignore_errors: get_flag(args, "ignore_errors"),
+            fail_fast: get_flag(args, "fail_fast"),
+            skip_special: get_flag(args, "skip_special"),
+            dedup: get_flag(args, "dedup"),
+            journal: get_opt(args, "journal"),
+            preserve_hardlinks: get_flag(args, "preserve_hardlinks"),
+            fsync: get_flag(args, "fsync"),
+            shard_index: get_flag(args, "shard_index"),
+            max_runtime: get_opt(args, "max_runtime"),
+            absolute_names: get_flag(args, "absolute_names"),
+            xattrs: get_flag(args, "xattrs"),
+            sparse: get_flag(args, "sparse"),
+            check_symlinks: get_flag(args, "check_symlinks"),
+            name_template: get_arg(args, "name_template"),
+            shard_dirs: get_list(args, "shard_dir"),
+            transform: get_opt(args, "transform"),
+            strip_root: get_flag(args, "strip_root"),
+            relative_to: get_opt(args, "relative_to"),
+            check_space: get_opt(args, "check_space"),
+            timing: Timing {
+                max_tries: get_arg(args, "max_retries"),
+                retry_wait: Duration::from_millis(get_arg(args, "retry_wait")),
+                collect_timeout: Duration::from_millis(
+                    get_arg(args, "collect_timeout")
+                )
+            },
+            quiet: get_flag(args, "quiet"),
+            progress_fd: get_opt(args, "progress_fd"),
+            progress_file: get_opt(args, "progress_file"),
+            verbose: get_flag(args, "verbose"),
+            list_threads: get_flag(args, "list_threads"),
+            dry_run: get_flag(args, "dry_run"),
+            test: get_flag(args, "test"),
+            benchmark: get_opt(args, "benchmark"),
+            benchmark_size: get_arg(args, "benchmark_size"),
+            json_errors: get_flag(args, "json_errors"),
+            to_stdout: get_flag(args, "to_stdout"),
+            only: get_opt(args, "only"),
+            files_from: get_opt(args, "files_from"),
+            on_conflict: ConflictPolicy::from_name(
+                & get_arg::<String>(args, "on_conflict")
+            ).unwrap(),
+            strip_components: get_arg(args, "strip_components"),
+            subdir: get_opt(args, "subdir"),
+            flatten: get_flag(args, "flatten"),
+            regular_only: get_flag(args, "regular_only"),
+            dereference_hardlinks: get_flag(args, "dereference_hardlinks"),
+            no_same_permissions: get_flag(args, "no_same_permissions"),
+            parallel_entries: get_arg(args, "parallel_entries"),
+            from_plan: get_opt(args, "from_plan"),
+            verify: get_opt(args, "verify"),
+            regenerate: get_flag(args, "regenerate"),
+            shard: get_opt(args, "shard")
+        }
+    }
+}
@@ -0,0 +1,93 @@
+// Recognizing compressed shards. This build reads and writes plain Tar only
+// (no compression crate is available), so a compressed shard is detected up
+// front and reported, instead of failing later with a confusing Tar parse
+// error.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Codec {
+    Plain,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2
+}
+
+
+impl Codec {
+    pub fn name(& self) -> & 'static str {
+        match self {
+            Codec::Plain => "plain",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Xz => "xz",
+            Codec::Bzip2 => "bzip2"
+        }
+    }
+
+    // The command that turns such a file back into plain Tar
+    pub fn decompressor(& self) -> & 'static str {
+        match self {
+            Codec::Plain => "cat",
+            Codec::Gzip => "gunzip",
+            Codec::Zstd => "unzstd",
+            Codec::Xz => "unxz",
+            Codec::Bzip2 => "bunzip2"
+        }
+    }
+
+    fn from_extension(path: & str) -> Option<Codec> {
+        let ext = path.rsplit('.').next()?;
+        match ext {
+            "gz" | "tgz" => Some(Codec::Gzip),
+            "zst" | "tzst" => Some(Codec::Zstd),
+            "xz" | "txz" => Some(Codec::Xz),
+            "bz2" | "tbz2" => Some(Codec::Bzip2),
+            "tar" => Some(Codec::Plain),
+            _ => None
+        }
+    }
+
+    pub fn from_magic(magic: & [u8]) -> Codec {
+        if magic.starts_with(& [0x1f, 0x8b]) {
+            Codec::Gzip
+        } else if magic.starts_with(& [0x28, 0xb5, 0x2f, 0xfd]) {
+            Codec::Zstd
+        } else if magic.starts_with(& [0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Codec::Xz
+        } else if magic.starts_with(b"BZh") {
+            Codec::Bzip2
+        } else {
+            Codec::Plain
+        }
+    }
+
+    // A compression extension decides; otherwise (including for `.tar`,
+    // which may have been compressed without renaming) the first bytes do
+    pub fn detect(path: & str) -> io::Result<Codec> {
+        match Codec::from_extension(path) {
+            Some(Codec::Plain) | None => {}
+            Some(codec) => return Ok(codec)
+        }
+        let mut magic = [0u8; 6];
+        let mut file = File::open(path)?;
+        let mut len = 0;
+        while len < magic.len() {
+            match file.read(& mut magic[len..])? {
+                0 => break,
+                n => len += n
+            }
+        }
+        Ok(Codec::from_magic(& magic[..len]))
+    }
+}
+
+
+impl fmt::Display for Codec {
+    fn fmt(& self, f: & mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,290 @@
+// Deduplicating create: of every set of regular files with identical
+// contents only the first is archived, and the others are recorded in
+// `{name}.dedup.json` as references to it. Extract materializes the
+// references once all shards are unpacked.
+use std::collections::HashMap;
+use std::fs::{
+    File, Metadata, Permissions, copy, create_dir_all, read_to_string,
+    remove_file, set_permissions, symlink_metadata, write
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tar::{Header, HeaderMode};
+
+use crate::error::ArchiverError;
+use crate::extract::{
+    ConflictPolicy, ExtractConfig, ExtractReport, destination_path
+};
+use crate::json::{Value, parse};
+use crate::sha256::hash_file;
+
+
+pub fn dedup_path(archive_name: & str) -> String {
+    format!("{}.dedup.json", archive_name)
+}
+
+
+// A path that was not archived, and the archived path with the same contents
+// (stored lossily in the references file if not UTF-8)
+pub struct Reference {
+    pub path: PathBuf,
+    pub original: PathBuf,
+    // What the header of `path` would have carried, had it been archived
+    // (None in references files written before these were recorded)
+    pub stat: Option<Stat>
+}
+
+
+// The metadata of a reference that extract restores
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stat {
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    pub mtime: u64
+}
+
+
+impl Stat {
+    // As a header written in `header_mode` would record `meta`
+    fn archived(meta: & Metadata, header_mode: HeaderMode) -> io::Result<Stat> {
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(meta, header_mode);
+        Ok(Stat {
+            mode: header.mode()?,
+            uid: header.uid()?,
+            gid: header.gid()?,
+            mtime: header.mtime()?
+        })
+    }
+}
+
+
+#[derive(Default)]
+pub struct References {
+    pub references: Vec<Reference>
+}
+
+
+// Split `work_items` into the items to archive and references for the
+// regular files whose contents an earlier item already has. Only files that
+// share their size with another file are hashed, and none larger than
+// `max_size` (the workers leave those out). References keep their own
+// metadata as `header_mode` would archive it.
+pub fn find_duplicates(
+        work_items: Vec<PathBuf>, max_size: Option<u64>,
+        header_mode: HeaderMode
+    ) -> (Vec<PathBuf>, References) {
+    let mut by_size: HashMap<u64, usize> = HashMap::new();
+    let mut sizes: Vec<Option<(u64, Stat)>> = Vec::new();
+    for item in & work_items {
+        let size = match symlink_metadata(item) {
+            Ok(meta) if meta.is_file() && meta.len() > 0
+                && max_size.is_none_or(|max| meta.len() <= max) => {
+                Stat::archived(& meta, header_mode).ok()
+                    .map(|stat| (meta.len(), stat))
+            }
+            _ => None
+        };
+        if let Some((size, _)) = size {
+            * by_size.entry(size).or_default() += 1;
+        }
+        sizes.push(size);
+    }
+
+    let mut first: HashMap<(u64, String), PathBuf> = HashMap::new();
+    let mut unique = Vec::new();
+    let mut references = References::default();
+    for (item, size) in work_items.into_iter().zip(sizes) {
+        let (size, stat) = match size {
+            Some((size, stat)) if by_size[& size] > 1 => (size, stat),
+            _ => {
+                unique.push(item);
+                continue;
+            }
+        };
+        // Unreadable files are archived (and reported) as usual
+        let digest = match hash_file(& item) {
+            Ok(digest) => digest,
+            Err(_) => {
+                unique.push(item);
+                continue;
+            }
+        };
+        match first.get(& (size, digest.clone())) {
+            Some(original) => references.references.push(Reference {
+                path: item, original: original.clone(), stat: Some(stat)
+            }),
+            None => {
+                first.insert((size, digest), item.clone());
+                unique.push(item);
+            }
+        }
+    }
+    (unique, references)
+}
+
+
+// Copy `original` to `target`, then give the copy the mode (masked with
+// `umask`, as extract does) and mtime of `stat`. A copy, not a hard link: the
+// reference may differ from its original in these, and is a file of its own
+// in the archived tree.
+fn materialize(
+        original: & Path, target: & Path, stat: Option<Stat>,
+        umask: Option<u32>
+    ) -> io::Result<()> {
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent)?;
+    }
+    copy(original, target)?;
+    let stat = match stat {
+        Some(stat) => stat,
+        None => return Ok(())
+    };
+    set_mode(target, stat.mode & 0o777 & ! umask.unwrap_or(0))?;
+    File::options().write(true).open(target)?
+        .set_modified(UNIX_EPOCH + Duration::from_secs(stat.mtime))?;
+    restore_owner(target, stat)
+}
+
+
+#[cfg(unix)]
+fn set_mode(target: & Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    set_permissions(target, Permissions::from_mode(mode))
+}
+
+
+#[cfg(not(unix))]
+fn set_mode(target: & Path, mode: u32) -> io::Result<()> {
+    let mut permissions = symlink_metadata(target)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    set_permissions(target, permissions)
+}
+
+
+// Only root can give a file away; others keep owning what they extract,
+// like the archived files
+#[cfg(unix)]
+fn restore_owner(target: & Path, stat: Stat) -> io::Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(());
+    }
+    std::os::unix::fs::chown(
+        target, Some(stat.uid as u32), Some(stat.gid as u32)
+    )
+}
+
+
+#[cfg(not(unix))]
+fn restore_owner(_: & Path, _: Stat) -> io::Result<()> {
+    Ok(())
+}
+
+
+fn mtime(path: & Path) -> io::Result<u64> {
+    Ok(symlink_metadata(path)?.modified()?
+        .duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()))
+}
+
+
+impl References {
+    pub fn to_json(& self) -> Value {
+        Value::object(vec![
+            ("references", Value::Array(
+                self.references.iter().map(|r| {
+                    let mut fields = vec![
+                        ("path", Value::str(& r.path.to_string_lossy())),
+                        ("original", Value::str(& r.original.to_string_lossy()))
+                    ];
+                    if let Some(stat) = r.stat {
+                        fields.extend([
+                            ("mode", Value::Int(stat.mode as u64)),
+                            ("uid", Value::Int(stat.uid)),
+                            ("gid", Value::Int(stat.gid)),
+                            ("mtime", Value::Int(stat.mtime))
+                        ]);
+                    }
+                    Value::object(fields)
+                }).collect()
+            ))
+        ])
+    }
+
+    pub fn from_json(json: & Value) -> Result<References, String> {
+        let mut references = Vec::new();
+        for r in json.get("references").and_then(|r| r.as_array())
+                .ok_or("'references' must be an array")? {
+            let field = |key: & str| r.get(key).and_then(|v| v.as_str())
+                .map(PathBuf::from)
+                .ok_or(format!("reference without '{}'", key));
+            let number = |key: & str| r.get(key).and_then(|v| v.as_u64());
+            let stat = match (
+                number("mode"), number("uid"), number("gid"), number("mtime")
+            ) {
+                (Some(mode), Some(uid), Some(gid), Some(mtime)) => Some(Stat {
+                    mode: mode as u32, uid, gid, mtime
+                }),
+                _ => None
+            };
+            references.push(Reference {
+                path: field("path")?, original: field("original")?, stat
+            });
+        }
+        Ok(References { references })
+    }
+
+    pub fn save(& self, path: & str) -> io::Result<()> {
+        write(path, self.to_json().pretty() + "\n")
+    }
+
+    pub fn load(path: & str) -> Result<References, String> {
+        let text = read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path, e))?;
+        References::from_json(& parse(& text)?)
+            .map_err(|e| format!("bad references {}: {}", path, e))
+    }
+
+    // Recreate every referenced file below `destination` from its extracted
+    // original, treating existing files like extract does
+    pub fn extract(
+            & self, destination: & str, config: & ExtractConfig
+        ) -> Result<ExtractReport, ArchiverError> {
+        let mut report = ExtractReport::default();
+        for r in & self.references {
+            let (rel, original) = match (
+                destination_path(& r.path, config),
+                destination_path(& r.original, config)
+            ) {
+                (Some(rel), Some(original)) => (rel, original),
+                _ => continue
+            };
+            let target = Path::new(destination).join(rel);
+            let original = Path::new(destination).join(original);
+            if symlink_metadata(& target).is_ok() {
+                let replace = match config.policy {
+                    ConflictPolicy::Overwrite => true,
+                    ConflictPolicy::Skip => false,
+                    ConflictPolicy::KeepNewer => {
+                        mtime(& original)? > mtime(& target)?
+                    }
+                    ConflictPolicy::Error => {
+                        return Err(ArchiverError::Conflict(
+                            target.to_string_lossy().to_string()
+                        ));
+                    }
+                };
+                if ! replace {
+                    report.skipped += 1;
+                    continue;
+                }
+                report.overwritten += 1;
+                remove_file(& target)?;
+            }
+            materialize(& original, & target, r.stat, config.umask)?;
+            report.entries += 1;
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,66 @@
+// How work items are assigned to shards (one shard per worker thread).
+use std::path::Path;
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Distribution {
+    // All workers pull from one shared queue. Fastest at balancing load, but
+    // which shard a file lands in depends on thread timing.
+    Shared,
+    // The shard is chosen by a stable hash of the file path, so the same
+    // file always lands in the same shard. Shards are balanced only by how
+    // the paths hash, not by size or worker speed.
+    PathHash,
+    // The shard is chosen by a stable hash of the parent directory, keeping
+    // siblings together. This improves restore locality at the cost of size
+    // balance.
+    ParentHash
+}
+
+
+impl Distribution {
+    pub fn is_shared(& self) -> bool {
+        * self == Distribution::Shared
+    }
+
+    pub fn name(& self) -> & 'static str {
+        match self {
+            Distribution::Shared => "shared",
+            Distribution::PathHash => "path",
+            Distribution::ParentHash => "dir"
+        }
+    }
+
+    pub fn from_name(name: & str) -> Option<Distribution> {
+        match name {
+            "shared" => Some(Distribution::Shared),
+            "path" => Some(Distribution::PathHash),
+            "dir" => Some(Distribution::ParentHash),
+            _ => None
+        }
+    }
+
+    // Paths are hashed as raw bytes (so UTF-8 paths are placed as before
+    // and other paths are placed as well)
+    pub fn shard_of(& self, path: & Path, num_shards: u32) -> usize {
+        let hashed = match self {
+            Distribution::Shared => return 0,
+            Distribution::PathHash => path,
+            Distribution::ParentHash => path.parent().unwrap_or(Path::new(""))
+        };
+        let hash = stable_hash(hashed.as_os_str().as_encoded_bytes());
+        (hash % num_shards as u64) as usize
+    }
+}
+
+
+// 64-bit FNV-1a. Unlike std's DefaultHasher its output is fixed across
+// platforms and compiler versions, which shard placement relies on.
+pub fn stable_hash(bytes: & [u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= * b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
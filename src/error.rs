@@ -0,0 +1,293 @@
+// Errors returned by the library API.
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::codec::Codec;
+use crate::json::Value;
+
+
+#[derive(Debug)]
+pub enum ArchiverError {
+    Io(io::Error),
+    // Enumerating the target failed
+    Walk(walkdir::Error),
+    // A plan or manifest fragment could not be read, or does not fit the
+    // request
+    Plan(String),
+    // A worker thread failed or stopped reporting progress
+    Worker(String),
+    // A shard is compressed, which this build cannot read
+    Compressed { path: String, codec: Codec },
+    // Extracting would overwrite this existing path (with --on-conflict
+    // error)
+    Conflict(String),
+    // A shard is not valid Tar from this byte on (found by extract --test)
+    Corrupt { path: String, offset: u64, reason: String },
+    // Create finished, but these items could not be archived
+    Failed(Vec<(PathBuf, ArchiverError)>),
+    // The options cannot be acted on (e.g. zero threads, or a shard the plan
+    // does not have): a mistake of the caller rather than a failure
+    InvalidArg(String),
+    // Creating or extracting this shard file failed
+    Shard { path: String, source: Box<ArchiverError> }
+}
+
+
+// Groups of failed items shown by Display (see group_errors); the full list
+// is in the variant
+const MAX_LISTED: usize = 20;
+
+
+impl fmt::Display for ArchiverError {
+    fn fmt(& self, f: & mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiverError::Io(e) => write!(f, "I/O error: {}", e),
+            ArchiverError::Walk(e) => write!(f, "cannot enumerate files: {}", e),
+            ArchiverError::Plan(msg) => write!(f, "{}", msg),
+            ArchiverError::Worker(msg) => write!(f, "worker failed: {}", msg),
+            ArchiverError::Compressed { path, codec } => write!(
+                f, "{} is {}-compressed, which is not supported; decompress \
+                it first (e.g. with {})", path, codec, codec.decompressor()
+            ),
+            ArchiverError::Conflict(path) => write!(
+                f, "refusing to overwrite existing {}", path
+            ),
+            ArchiverError::Corrupt { path, offset, reason } => write!(
+                f, "{} is corrupt at byte {}: {}", path, offset, reason
+            ),
+            ArchiverError::Failed(failed) => {
+                write!(f, "{} files could not be archived:", failed.len())?;
+                let groups = group_errors(failed);
+                for group in groups.iter().take(MAX_LISTED) {
+                    if group.count == 1 {
+                        write!(
+                            f, "\n  {}: {}", group.example.display(),
+                            group.error
+                        )?;
+                    } else {
+                        write!(
+                            f, "\n  {} files: {} under {} (e.g. {}: {})",
+                            group.count, group.cause, group.under.display(),
+                            group.example.display(), group.error
+                        )?;
+                    }
+                }
+                if groups.len() > MAX_LISTED {
+                    let rest: usize = groups[MAX_LISTED..].iter()
+                        .map(|group| group.count)
+                        .sum();
+                    write!(f, "\n  ... and {} more", rest)?;
+                }
+                Ok(())
+            }
+            ArchiverError::InvalidArg(msg) => write!(f, "invalid argument: {}", msg),
+            ArchiverError::Shard { path, source } => write!(f, "{}: {}", path, source)
+        }
+    }
+}
+
+
+impl Error for ArchiverError {
+    fn source(& self) -> Option<& (dyn Error + 'static)> {
+        match self {
+            ArchiverError::Io(e) => Some(e),
+            ArchiverError::Walk(e) => Some(e),
+            ArchiverError::Shard { source, .. } => Some(source.as_ref()),
+            _ => None
+        }
+    }
+}
+
+
+impl ArchiverError {
+    // What went wrong, without the path it went wrong on, for grouping
+    // items that failed the same way (see group_errors)
+    pub fn failure_kind(& self) -> String {
+        match self {
+            ArchiverError::Io(e) if e.kind() != io::ErrorKind::Other => {
+                e.kind().to_string()
+            }
+            ArchiverError::Walk(e) => match e.io_error() {
+                Some(e) if e.kind() != io::ErrorKind::Other => {
+                    e.kind().to_string()
+                }
+                _ => e.to_string()
+            },
+            ArchiverError::Shard { source, .. } => source.failure_kind(),
+            _ => self.to_string()
+        }
+    }
+
+    // A stable name for the variant, for scripts (see to_json). The error
+    // in a shard is named, not the shard wrapper around it.
+    pub fn error_kind(& self) -> & 'static str {
+        match self {
+            ArchiverError::Io(_) => "io",
+            ArchiverError::Walk(_) => "walk",
+            ArchiverError::Plan(_) => "plan",
+            ArchiverError::Worker(_) => "worker",
+            ArchiverError::Compressed { .. } => "compressed",
+            ArchiverError::Conflict(_) => "conflict",
+            ArchiverError::Corrupt { .. } => "corrupt",
+            ArchiverError::Failed(_) => "failed",
+            ArchiverError::InvalidArg(_) => "invalid_argument",
+            ArchiverError::Shard { source, .. } => source.error_kind()
+        }
+    }
+
+    // The file the error is about, if it names one
+    pub fn path(& self) -> Option<String> {
+        match self {
+            ArchiverError::Walk(e) => {
+                e.path().map(|p| p.to_string_lossy().to_string())
+            }
+            ArchiverError::Compressed { path, .. }
+                | ArchiverError::Conflict(path)
+                | ArchiverError::Corrupt { path, .. }
+                | ArchiverError::Shard { path, .. } => Some(path.clone()),
+            _ => None
+        }
+    }
+
+    // `{"error_kind", "message", "path"}` (path only if there is one), and
+    // for Failed every item that could not be archived
+    pub fn to_json(& self) -> Value {
+        let mut fields = vec![
+            ("error_kind", Value::str(self.error_kind())),
+            ("message", Value::str(& self.to_string()))
+        ];
+        if let Some(path) = self.path() {
+            fields.push(("path", Value::String(path)));
+        }
+        if let ArchiverError::Failed(failed) = self {
+            fields.push(("failed", Value::Array(
+                failed.iter().map(|(path, error)| Value::object(vec![
+                    ("path", Value::str(& path.to_string_lossy())),
+                    ("error_kind", Value::str(error.error_kind())),
+                    ("message", Value::str(& error.to_string()))
+                ])).collect()
+            )));
+        }
+        Value::object(fields)
+    }
+
+    // Attach the shard file `path` to an error from working on it, unless
+    // the error names it already
+    pub fn in_shard(self, path: & str) -> ArchiverError {
+        match self {
+            ArchiverError::Compressed { .. } | ArchiverError::Corrupt { .. }
+                | ArchiverError::Shard { .. } => self,
+            _ => ArchiverError::Shard {
+                path: path.to_string(), source: Box::new(self)
+            }
+        }
+    }
+}
+
+
+impl From<io::Error> for ArchiverError {
+    fn from(e: io::Error) -> ArchiverError {
+        ArchiverError::Io(e)
+    }
+}
+
+
+impl From<walkdir::Error> for ArchiverError {
+    fn from(e: walkdir::Error) -> ArchiverError {
+        ArchiverError::Walk(e)
+    }
+}
+
+
+// Items that failed with the same cause, e.g. everything below an unreadable
+// directory
+pub struct ErrorGroup {
+    pub cause: String,
+    // The deepest directory (or the path, for one item) all of them are in
+    pub under: PathBuf,
+    pub count: usize,
+    // The first of them, and its full error
+    pub example: PathBuf,
+    pub error: String
+}
+
+
+fn common_ancestor(a: & Path, b: & Path) -> PathBuf {
+    a.components().zip(b.components())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a)
+        .collect()
+}
+
+
+// Group `errors` by ArchiverError::failure_kind, largest group first, so
+// that a problem with a whole subtree is one line rather than thousands
+pub fn group_errors<'a, I>(errors: I) -> Vec<ErrorGroup>
+        where I: IntoIterator<Item = & 'a (PathBuf, ArchiverError)> {
+    let mut groups: Vec<ErrorGroup> = Vec::new();
+    for (path, error) in errors {
+        let cause = error.failure_kind();
+        match groups.iter_mut().find(|group| group.cause == cause) {
+            Some(group) => {
+                group.under = common_ancestor(& group.under, path);
+                group.count += 1;
+            }
+            None => groups.push(ErrorGroup {
+                cause, under: path.clone(), count: 1, example: path.clone(),
+                error: error.to_string()
+            })
+        }
+    }
+    groups.sort_by_key(|group| std::cmp::Reverse(group.count));
+    groups
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn denied(path: & str) -> (PathBuf, ArchiverError) {
+        let error = io::Error::new(
+            io::ErrorKind::PermissionDenied, format!("cannot open {}", path)
+        );
+        (PathBuf::from(path), ArchiverError::Io(error))
+    }
+
+    #[test]
+    fn failed_groups_items_that_failed_the_same_way() {
+        let mut failed: Vec<_> = (0..30)
+            .map(|idx| denied(& format!("t/d/f{}", idx)))
+            .collect();
+        failed.push((
+            PathBuf::from("t/g"), ArchiverError::Plan("vanished".to_string())
+        ));
+        let shown = ArchiverError::Failed(failed).to_string();
+        let lines: Vec<& str> = shown.lines().collect();
+        assert_eq!(lines, [
+            "31 files could not be archived:",
+            "  30 files: permission denied under t/d \
+            (e.g. t/d/f0: I/O error: cannot open t/d/f0)",
+            "  t/g: vanished"
+        ]);
+    }
+
+    #[test]
+    fn failed_counts_the_items_of_unlisted_groups() {
+        let mut failed: Vec<_> = (0..MAX_LISTED + 2)
+            .map(|idx| (
+                PathBuf::from(format!("f{}", idx)),
+                ArchiverError::Plan(format!("reason {}", idx))
+            ))
+            .collect();
+        failed.push(denied("g0"));
+        failed.push(denied("g1"));
+        let shown = ArchiverError::Failed(failed).to_string();
+        let lines: Vec<& str> = shown.lines().collect();
+        assert_eq!(lines.len(), MAX_LISTED + 2);
+        assert!(lines[1].starts_with("  2 files: permission denied under "));
+        assert_eq!(lines[MAX_LISTED + 1], "  ... and 3 more");
+    }
+}
@@ -0,0 +1,206 @@
+// Shell-style exclude patterns (`*`, `**`, `?`) matched against paths
+// relative to the archive target, and `.ptarignore` files using the same
+// syntax as `.gitignore`.
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+
+pub const IGNORE_FILE: &str = ".ptarignore";
+
+
+// The version control directories and files GNU tar's --exclude-vcs leaves
+// out, matched by name at any depth
+pub const VCS_EXCLUDES: & [& str] = & [
+    "CVS", "RCS", "SCCS", ".git", ".gitignore", ".gitattributes",
+    ".gitmodules", ".cvsignore", ".svn", ".arch-ids", "{arch}",
+    "=RELEASE-ID", "=meta-update", "=update", ".bzr", ".bzrignore",
+    ".bzrtags", ".hg", ".hgignore", ".hgtags", "_darcs"
+];
+
+
+pub struct Pattern {
+    glob: String,
+    // Patterns containing a `/` are matched against the whole relative path,
+    // all others against the entry's file name (at any depth).
+    anchored: bool
+}
+
+
+impl Pattern {
+    pub fn new(glob: & str) -> Pattern {
+        let glob = glob.trim_start_matches("./");
+        let anchored = glob.trim_end_matches('/').contains('/');
+        Pattern {
+            glob: glob.trim_start_matches('/').trim_end_matches('/').to_string(),
+            anchored
+        }
+    }
+
+    pub fn matches(& self, rel_path: & str) -> bool {
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), rel_path.as_bytes())
+        } else {
+            let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+
+pub struct Rule {
+    pattern: Pattern,
+    // `!pattern` re-includes something an earlier rule excluded
+    negate: bool,
+    // `pattern/` only applies to directories
+    dir_only: bool
+}
+
+
+impl Rule {
+    pub fn new(line: & str) -> Rule {
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line))
+        };
+        Rule {
+            pattern: Pattern::new(line),
+            negate,
+            dir_only: line.ends_with('/')
+        }
+    }
+
+    // Returns Some(true) to exclude, Some(false) to re-include, or None if
+    // the rule does not apply to this path
+    pub fn decide(& self, rel_path: & str, is_dir: bool) -> Option<bool> {
+        if self.dir_only && ! is_dir {
+            return None;
+        }
+        if self.pattern.matches(rel_path) {
+            Some(! self.negate)
+        } else {
+            None
+        }
+    }
+}
+
+
+fn parse_rules(text: & str) -> Vec<Rule> {
+    text.lines()
+        .map(|l| l.trim_end())
+        .filter(|l| ! l.is_empty() && ! l.starts_with('#'))
+        .map(Rule::new)
+        .collect()
+}
+
+
+// Later rules take precedence over earlier ones, as in .gitignore
+fn decide_all(rules: & [Rule], rel_path: & str, is_dir: bool) -> Option<bool> {
+    rules.iter().rev().find_map(|r| r.decide(rel_path, is_dir))
+}
+
+
+#[derive(Default)]
+pub struct ExcludeSet {
+    rules: Vec<Rule>
+}
+
+
+impl ExcludeSet {
+    pub fn new(globs: & [String]) -> ExcludeSet {
+        ExcludeSet {
+            rules: globs.iter().map(|g| Rule::new(g)).collect()
+        }
+    }
+
+    pub fn is_empty(& self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_excluded(& self, rel_path: & str, is_dir: bool) -> bool {
+        decide_all(& self.rules, rel_path, is_dir).unwrap_or(false)
+    }
+}
+
+
+// Lazily loads `.ptarignore` files below `root`. Each file's rules apply to
+// paths relative to its own directory, and files deeper in the tree take
+// precedence over their parents.
+pub struct IgnoreFiles {
+    root: PathBuf,
+    cache: HashMap<PathBuf, Vec<Rule>>
+}
+
+
+impl IgnoreFiles {
+    pub fn new(root: & Path) -> IgnoreFiles {
+        IgnoreFiles {
+            root: root.to_path_buf(),
+            cache: HashMap::new()
+        }
+    }
+
+    fn rules_for(& mut self, dir: & Path) -> & [Rule] {
+        self.cache.entry(dir.to_path_buf()).or_insert_with(|| {
+            read_to_string(dir.join(IGNORE_FILE))
+                .map(|text| parse_rules(& text))
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn is_ignored(& mut self, path: & Path, is_dir: bool) -> bool {
+        let rel = match path.strip_prefix(& self.root) {
+            Ok(rel) => rel.to_path_buf(),
+            Err(_) => return false
+        };
+
+        let mut decision = None;
+        let mut dir = self.root.clone();
+        let components: Vec<_> = rel.components().collect();
+        for (idx, component) in components.iter().enumerate() {
+            let below: PathBuf = components[idx..].iter().collect();
+            let below = below.to_string_lossy().to_string();
+            if let Some(d) = decide_all(self.rules_for(& dir), & below, is_dir) {
+                decision = Some(d);
+            }
+            dir.push(component);
+        }
+        decision.unwrap_or(false)
+    }
+}
+
+
+fn glob_match(pat: & [u8], text: & [u8]) -> bool {
+    match pat.first() {
+        None => text.is_empty(),
+        Some(b'*') if pat.get(1) == Some(& b'*') => {
+            // `**/` also matches zero directories
+            if pat.get(2) == Some(& b'/') && glob_match(& pat[3..], text) {
+                return true;
+            }
+            // `**` matches anything, including `/`
+            (0..=text.len()).any(|i| glob_match(& pat[2..], & text[i..]))
+        }
+        Some(b'*') => {
+            // `*` does not cross directory boundaries
+            for i in 0..=text.len() {
+                if glob_match(& pat[1..], & text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == b'/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => {
+            match text.first() {
+                Some(c) if * c != b'/' => glob_match(& pat[1..], & text[1..]),
+                _ => false
+            }
+        }
+        Some(c) => {
+            text.first() == Some(c) && glob_match(& pat[1..], & text[1..])
+        }
+    }
+}
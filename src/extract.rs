@@ -0,0 +1,849 @@
+// Unpacking a shard entry by entry, so that every destination path can be
+// checked against the conflict policy before it is written.
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{
+    File, canonicalize, copy, create_dir_all, hard_link, remove_file,
+    symlink_metadata
+};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, UNIX_EPOCH};
+use tar::{Archive, Builder, Entry, EntryType};
+
+use crate::codec::Codec;
+use crate::error::ArchiverError;
+use crate::journal::Journal;
+use crate::shard::{NameTemplate, SHARD_INDEX, SHARD_INDEX_RECORD};
+use crate::xattrs::{self, append_pax, entry_records};
+
+
+// What to do when an entry's destination already exists. Directories are
+// always merged into.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    // Overwrite only if the archived entry is newer than the existing file
+    KeepNewer,
+    Error
+}
+
+
+impl ConflictPolicy {
+    pub fn name(& self) -> & 'static str {
+        match self {
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::KeepNewer => "keep-newer",
+            ConflictPolicy::Error => "error"
+        }
+    }
+
+    pub fn from_name(name: & str) -> Option<ConflictPolicy> {
+        match name {
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            "skip" => Some(ConflictPolicy::Skip),
+            "keep-newer" => Some(ConflictPolicy::KeepNewer),
+            "error" => Some(ConflictPolicy::Error),
+            _ => None
+        }
+    }
+}
+
+
+// How every shard is extracted
+#[derive(Clone, Default)]
+pub struct ExtractConfig {
+    pub policy: ConflictPolicy,
+    // Leading path components dropped from every entry
+    pub strip: usize,
+    // Threads writing the files of each part; above 1 see WriterPool
+    pub entry_threads: u32,
+    // Restore extended attributes recorded in PAX headers
+    pub xattrs: bool,
+    // Write every file straight into the destination, under its file name
+    // only (`strip` is ignored, directories are not created)
+    pub flatten: bool,
+    // Mask the archived modes with this umask (None restores them as
+    // archived, without set-user-ID and similar bits)
+    pub umask: Option<u32>,
+    // Extract only regular files, directories and hard links, leaving out
+    // devices, FIFOs and symlinks (see is_special)
+    pub regular_only: bool,
+    // Extract only the entries at or below this (relative, normalized)
+    // path, matched before `strip`
+    pub subdir: Option<PathBuf>,
+    // Write hard links as copies of their originals (for file systems
+    // without hard links)
+    pub dereference_hardlinks: bool,
+    // Record every entry written, and skip those an interrupted run
+    // recorded (see Archiver::extract)
+    pub journal: Option<Arc<Mutex<Journal>>>
+}
+
+
+impl ExtractConfig {
+    // Whether entries are written somewhere else than their archived path
+    fn rewrites_paths(& self) -> bool {
+        self.strip > 0 || self.flatten
+    }
+
+    // Whether an earlier, interrupted run wrote the entry named `member`
+    // (its raw header path) to `target`: it is in the journal, and the
+    // target is still there
+    fn written_before(& self, member: & [u8], target: & Path) -> bool {
+        self.journal.as_ref().is_some_and(|journal| {
+            journal.lock().unwrap().contains(member)
+        }) && symlink_metadata(target).is_ok()
+    }
+
+    // Note in the journal (if any) that the entry named `member` of shard
+    // `shard` is written
+    fn record(& self, shard: u32, member: & [u8]) -> Result<(), ArchiverError> {
+        if let Some(journal) = & self.journal {
+            journal.lock().unwrap().record(shard, member)?;
+        }
+        Ok(())
+    }
+}
+
+
+// What extract wrote, and the entries whose destination already existed
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ExtractReport {
+    // Entries unpacked, and the bytes of file data among them
+    pub entries: u64,
+    pub bytes: u64,
+    pub skipped: u64,
+    pub overwritten: u64,
+    // Devices, FIFOs and symlinks left out by `regular_only`
+    pub special: u64,
+    // Hard links written as copies by `dereference_hardlinks`
+    pub dereferenced: u64,
+    // Wall time of the whole extract (set by Archiver::extract)
+    pub elapsed: Duration
+}
+
+
+impl ExtractReport {
+    pub fn add(& mut self, other: & ExtractReport) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self.skipped += other.skipped;
+        self.overwritten += other.overwritten;
+        self.special += other.special;
+        self.dereferenced += other.dereferenced;
+    }
+}
+
+
+// The index entry create --shard-index ends every part with, which is not
+// extracted: its name, marked by a SHARD_INDEX_RECORD (so that a member of
+// the tree that happens to have the name is extracted like any other)
+fn is_shard_index<R: Read>(entry: & mut Entry<R>) -> bool {
+    let (marker, comment) = SHARD_INDEX_RECORD;
+    entry.path_bytes().as_ref() == SHARD_INDEX.as_bytes()
+        && entry_records(entry).is_ok_and(|records| records.iter()
+            .any(|(key, value)| key == marker.as_bytes()
+                && value == comment.as_bytes()))
+}
+
+
+// Entries `regular_only` leaves out: those a restore without root (or onto a
+// file system without them) may fail to create
+fn is_special(kind: EntryType) -> bool {
+    matches!(
+        kind,
+        EntryType::Char | EntryType::Block | EntryType::Fifo
+            | EntryType::Symlink
+    )
+}
+
+
+// The path an entry is written to, relative to the destination, after
+// dropping its first `strip` components. None if nothing is left, or if the
+// path tries to leave the destination (those entries are skipped, as
+// Entry::unpack_in does).
+pub fn strip_path(path: & Path, strip: usize) -> Option<PathBuf> {
+    let mut normal: Vec<& std::ffi::OsStr> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normal.push(part),
+            Component::ParentDir => return None,
+            // Leading "/" and "./" are dropped
+            Component::RootDir | Component::Prefix(_) | Component::CurDir => {}
+        }
+    }
+    if normal.len() <= strip {
+        return None;
+    }
+    Some(normal[strip..].iter().collect())
+}
+
+
+// The path a (non-directory) entry is written to, relative to the
+// destination: see strip_path, or the bare file name with `flatten`. None
+// for entries outside of `subdir`.
+pub fn destination_path(path: & Path, config: & ExtractConfig) -> Option<PathBuf> {
+    if let Some(subdir) = & config.subdir {
+        if ! strip_path(path, 0)?.starts_with(subdir) {
+            return None;
+        }
+    }
+    rewrite_path(path, config)
+}
+
+
+// Where `path` goes with `strip` or `flatten` applied
+fn rewrite_path(path: & Path, config: & ExtractConfig) -> Option<PathBuf> {
+    if config.flatten {
+        strip_path(path, 0)?.file_name().map(PathBuf::from)
+    } else {
+        strip_path(path, config.strip)
+    }
+}
+
+
+// Write a (stripped) entry to `target` below `destination`, refusing to
+// follow symlinks out of the destination like Entry::unpack_in does
+fn unpack_to<R: Read>(
+        entry: & mut Entry<R>, destination: & str, target: & Path
+    ) -> Result<(), ArchiverError> {
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent)?;
+        if ! canonicalize(parent)?.starts_with(canonicalize(destination)?) {
+            return Err(ArchiverError::Io(std::io::Error::other(format!(
+                "{} would be written outside of {}",
+                target.display(), destination
+            ))));
+        }
+    }
+    entry.unpack(target)?;
+    Ok(())
+}
+
+
+// What extract does with an entry, given the policy and what is at its
+// destination
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Create,
+    Overwrite,
+    Skip,
+    // The destination exists and the policy is `error`
+    Conflict
+}
+
+
+impl Action {
+    pub fn name(& self) -> & 'static str {
+        match self {
+            Action::Create => "create",
+            Action::Overwrite => "overwrite",
+            Action::Skip => "skip",
+            Action::Conflict => "conflict"
+        }
+    }
+}
+
+
+fn action<R: Read>(
+        entry: & Entry<R>, target: & Path, policy: ConflictPolicy
+    ) -> Result<Action, ArchiverError> {
+    let existing = match symlink_metadata(target) {
+        Ok(meta) if ! meta.is_dir() => meta,
+        _ => return Ok(Action::Create)
+    };
+
+    let replace = match policy {
+        ConflictPolicy::Overwrite => true,
+        ConflictPolicy::Skip => false,
+        ConflictPolicy::KeepNewer => {
+            let existing_mtime = existing.modified()?
+                .duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            entry.header().mtime()? > existing_mtime
+        }
+        ConflictPolicy::Error => return Ok(Action::Conflict)
+    };
+    Ok(if replace { Action::Overwrite } else { Action::Skip })
+}
+
+
+// Whether to write `entry` to `target`, given the policy and what is there
+fn should_unpack<R: Read>(
+        entry: & Entry<R>, target: & Path, policy: ConflictPolicy,
+        report: & mut ExtractReport
+    ) -> Result<bool, ArchiverError> {
+    match action(entry, target, policy)? {
+        Action::Create => Ok(true),
+        Action::Overwrite => {
+            report.overwritten += 1;
+            Ok(true)
+        }
+        Action::Skip => {
+            report.skipped += 1;
+            Ok(false)
+        }
+        Action::Conflict => Err(ArchiverError::Conflict(
+            target.to_string_lossy().to_string()
+        ))
+    }
+}
+
+
+// The umask of this process (0 where there is none)
+#[cfg(unix)]
+pub fn process_umask() -> u32 {
+    // Reading the umask means setting it: put it back right away
+    let mask = unsafe { libc::umask(0o022) };
+    unsafe { libc::umask(mask) };
+    mask as u32
+}
+
+
+#[cfg(not(unix))]
+pub fn process_umask() -> u32 {
+    0
+}
+
+
+// Give an unpacked entry its archived mode masked with `umask`, like
+// `tar --no-same-permissions`. Symlinks have no mode of their own, and hard
+// links share the mode of the file they link to.
+#[cfg(unix)]
+fn apply_umask(
+        target: & Path, kind: EntryType, mode: u32, umask: u32
+    ) -> io::Result<()> {
+    use std::fs::{Permissions, set_permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    if kind.is_symlink() || kind.is_hard_link() {
+        return Ok(());
+    }
+    set_permissions(target, Permissions::from_mode(mode & 0o777 & ! umask))
+}
+
+
+#[cfg(not(unix))]
+fn apply_umask(_: & Path, _: EntryType, _: u32, _: u32) -> io::Result<()> {
+    Ok(())
+}
+
+
+// `target` is where destination_path puts the entry.
+// Entries written to their archived path keep using Entry::unpack_in, which
+// also resolves hard link targets against the destination
+fn unpack_entry<R: Read>(
+        mut entry: Entry<R>, destination: & str, target: & Path,
+        config: & ExtractConfig
+    ) -> Result<(), ArchiverError> {
+    if ! config.rewrites_paths() {
+        // Entries it skips (leaving the destination) are skipped here too
+        if ! entry.unpack_in(destination)? {
+            return Ok(());
+        }
+    } else {
+        unpack_to(& mut entry, destination, target)?;
+    }
+    mask_entry(& entry, target, config)
+}
+
+
+// With `umask`, give the entry unpacked to `target` its masked mode
+fn mask_entry<R: Read>(
+        entry: & Entry<R>, target: & Path, config: & ExtractConfig
+    ) -> Result<(), ArchiverError> {
+    if let Some(umask) = config.umask {
+        let kind = entry.header().entry_type();
+        apply_umask(target, kind, entry.header().mode()?, umask)?;
+    }
+    Ok(())
+}
+
+
+// Files waiting for a writer, per writer thread. Every one holds a whole
+// file in memory.
+const QUEUED_PER_WRITER: usize = 4;
+
+
+// One file entry, copied out of the shard as a single-entry Tar (so that the
+// writer unpacks it like the sequential path does). The entry is written to
+// `target`, already resolved against the destination, so its name in the
+// single-entry Tar does not matter.
+struct Buffered {
+    tar: Vec<u8>,
+    target: PathBuf
+}
+
+
+fn write_buffered(
+        rx: Receiver<Buffered>, destination: & str, config: ExtractConfig
+    ) -> Result<(), ArchiverError> {
+    for buffered in rx {
+        let mut ar = Archive::new(Cursor::new(buffered.tar));
+        ar.set_unpack_xattrs(config.xattrs);
+        if let Some(entry) = ar.entries()?.next() {
+            let mut entry = entry?;
+            unpack_to(& mut entry, destination, & buffered.target)?;
+            mask_entry(& entry, & buffered.target, & config)?;
+        }
+    }
+    Ok(())
+}
+
+
+// Writer threads for the regular files of a shard, fed by the thread reading
+// the shard. Entries are routed by destination path, so when a path occurs
+// more than once (e.g. after an append) the last entry still wins.
+struct WriterPool {
+    senders: Vec<SyncSender<Buffered>>,
+    handles: Vec<JoinHandle<Result<(), ArchiverError>>>
+}
+
+
+impl WriterPool {
+    fn new(destination: & str, config: ExtractConfig) -> WriterPool {
+        let mut senders = Vec::new();
+        let mut handles = Vec::new();
+        for _ in 0..config.entry_threads {
+            let (tx, rx) = sync_channel(QUEUED_PER_WRITER);
+            let destination = destination.to_string();
+            let config = config.clone();
+            senders.push(tx);
+            handles.push(thread::spawn(move || {
+                write_buffered(rx, & destination, config)
+            }));
+        }
+        WriterPool { senders, handles }
+    }
+
+    fn dispatch<R: Read>(
+            & self, mut entry: Entry<R>, target: PathBuf
+        ) -> Result<(), ArchiverError> {
+        // PAX records (e.g. xattrs) have to travel with the entry, but not
+        // its name: Builder refuses absolute names and `..`
+        let pax: Vec<_> = entry_records(& mut entry)?.into_iter()
+            .filter(|(key, _)| key != b"path")
+            .collect();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(& mut data)?;
+        let mut header = entry.header().clone();
+        header.set_size(data.len() as u64);
+        let mut builder = Builder::new(Vec::new());
+        append_pax(& mut builder, & pax)?;
+        builder.append_data(& mut header, "entry", data.as_slice())?;
+        let tar = builder.into_inner()?;
+
+        let mut hasher = DefaultHasher::new();
+        target.hash(& mut hasher);
+        let writer = (hasher.finish() % self.senders.len() as u64) as usize;
+        // A writer that failed has dropped its receiver; its error is
+        // returned by finish
+        let _ = self.senders[writer].send(Buffered { tar, target });
+        Ok(())
+    }
+
+    // Wait until every dispatched file is written, returning the first
+    // error of any writer
+    fn finish(self) -> Result<(), ArchiverError> {
+        drop(self.senders);
+        let mut failure = None;
+        for handle in self.handles {
+            let result = handle.join().unwrap_or_else(|_| Err(
+                ArchiverError::Worker("entry writer panicked".to_string())
+            ));
+            if let Err(error) = result {
+                failure.get_or_insert(error);
+            }
+        }
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(())
+        }
+    }
+}
+
+
+// A hard link whose original had not been unpacked when the link was read
+// (it is in a later part or in another shard)
+pub struct DeferredLink {
+    pub original: PathBuf,
+    pub target: PathBuf,
+    // Copy the original instead of linking to it
+    pub copy: bool,
+    // The shard the link is in, and the raw header path of its entry
+    pub shard: u32,
+    pub member: Vec<u8>
+}
+
+
+// Where the original of a Link entry is unpacked to (None if the entry has
+// no usable link name)
+fn link_original<R: Read>(
+        entry: & Entry<R>, destination: & str, config: & ExtractConfig
+    ) -> Result<Option<PathBuf>, ArchiverError> {
+    // The original may be outside of `subdir` (link_deferred then reports
+    // it missing)
+    Ok(entry.link_name()?
+        .and_then(|name| rewrite_path(& name, config))
+        .map(|rel| Path::new(destination).join(rel)))
+}
+
+
+// Hard links are unpacked after the part's other files, if their original is
+// among the paths this shard `written` so far; the others are added to
+// `deferred` (whatever is at the original now may be stale, or not written
+// yet by another shard). Entries are recorded in the journal once they are
+// written (shard `idx`).
+fn extract_part(
+        tar_path: & str, idx: u32, destination: & str, config: ExtractConfig,
+        report: & mut ExtractReport, deferred: & mut Vec<DeferredLink>,
+        written: & mut HashSet<PathBuf>
+    ) -> Result<(), ArchiverError> {
+    // Parts must be plain Tar: detect compression before unpacking
+    // garbage
+    let codec = Codec::detect(tar_path)?;
+    if codec != Codec::Plain {
+        return Err(ArchiverError::Compressed {
+            path: tar_path.to_string(), codec
+        });
+    }
+    let mut ar = Archive::new(File::open(tar_path)?);
+    ar.set_unpack_xattrs(config.xattrs);
+    let pool = if config.entry_threads > 1 {
+        Some(WriterPool::new(destination, config.clone()))
+    } else {
+        None
+    };
+
+    // As in Archive::unpack, directories are unpacked last, so that a
+    // read-only directory does not prevent writing its contents. Hard links
+    // wait until the files they point to are written.
+    let mut directories = Vec::new();
+    let mut links = Vec::new();
+    // Files the pool writes, recorded once it is done
+    let mut pooled = Vec::new();
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        if is_shard_index(& mut entry)
+                || (config.flatten && entry.header().entry_type().is_dir()) {
+            continue;
+        }
+        if config.regular_only && is_special(entry.header().entry_type()) {
+            report.special += 1;
+            continue;
+        }
+        let rel = match destination_path(& entry.path()?, & config) {
+            Some(rel) => rel,
+            None => continue
+        };
+        let target = Path::new(destination).join(rel);
+        let member = entry.path_bytes().into_owned();
+        if config.written_before(& member, & target) {
+            report.skipped += 1;
+            continue;
+        }
+        if ! should_unpack(& entry, & target, config.policy, report)? {
+            continue;
+        }
+        report.entries += 1;
+        if entry.header().entry_type().is_file() {
+            report.bytes += entry.size();
+        }
+        match (entry.header().entry_type(), & pool) {
+            (EntryType::Directory, _) => {
+                directories.push((entry, member, target));
+            }
+            (EntryType::Regular | EntryType::Continuous, Some(pool)) => {
+                pool.dispatch(entry, target.clone())?;
+                pooled.push((member, target));
+            }
+            (EntryType::Link, _) => links.push((entry, member, target)),
+            _ => {
+                unpack_entry(entry, destination, & target, & config)?;
+                config.record(idx, & member)?;
+                written.insert(target);
+            }
+        }
+    }
+    if let Some(pool) = pool {
+        pool.finish()?;
+    }
+    for (member, target) in pooled {
+        config.record(idx, & member)?;
+        written.insert(target);
+    }
+    let copy = config.dereference_hardlinks;
+    for (entry, member, target) in links {
+        let original = link_original(& entry, destination, & config)?;
+        if copy && original.is_some() {
+            report.dereferenced += 1;
+        }
+        match original {
+            Some(original) if ! written.contains(& original) => {
+                deferred.push(DeferredLink {
+                    original, target, copy, shard: idx, member
+                });
+                continue;
+            }
+            // Entry::unpack would take the link name as it is, unstripped,
+            // and fails on a file already at the target
+            Some(original) => {
+                create_link(& DeferredLink {
+                    original, target: target.clone(), copy, shard: idx,
+                    member: member.clone()
+                })?;
+            }
+            _ => unpack_entry(entry, destination, & target, & config)?
+        }
+        config.record(idx, & member)?;
+        written.insert(target);
+    }
+    for (mut dir, member, target) in directories {
+        let records = if config.xattrs {
+            entry_records(& mut dir)?
+        } else {
+            Vec::new()
+        };
+        unpack_entry(dir, destination, & target, & config)?;
+        xattrs::restore(& target, & records)?;
+        config.record(idx, & member)?;
+    }
+    Ok(())
+}
+
+
+// `config.entry_threads` above 1 writes the regular files of every part with
+// that many threads (see WriterPool), for shards much larger than the others
+// Returns the hard links left for link_deferred.
+pub fn extract_shard(
+        archive_name: & str, names: & NameTemplate, idx: u32,
+        destination: & str, config: ExtractConfig
+    ) -> Result<(ExtractReport, Vec<DeferredLink>), ArchiverError> {
+    let mut report = ExtractReport::default();
+    let mut deferred = Vec::new();
+    let mut written = HashSet::new();
+    for tar_path in names.parts(archive_name, idx) {
+        extract_part(
+            & tar_path, idx, destination, config.clone(), & mut report,
+            & mut deferred, & mut written
+        ).map_err(|e| e.in_shard(& tar_path))?;
+    }
+    Ok((report, deferred))
+}
+
+
+// Replaces what is at the target: the entry already passed the conflict
+// policy
+fn create_link(link: & DeferredLink) -> Result<(), ArchiverError> {
+    if symlink_metadata(& link.target).is_ok() {
+        remove_file(& link.target)?;
+    } else if let Some(parent) = link.target.parent() {
+        create_dir_all(parent)?;
+    }
+    if link.copy {
+        copy(& link.original, & link.target).map_err(|error| {
+            io::Error::new(error.kind(), format!(
+                "cannot copy {} to {}: {}",
+                link.original.display(), link.target.display(), error
+            ))
+        })?;
+        return Ok(());
+    }
+    hard_link(& link.original, & link.target).map_err(|error| {
+        io::Error::new(error.kind(), format!(
+            "cannot link {} to {}: {}",
+            link.target.display(), link.original.display(), error
+        ))
+    })?;
+    Ok(())
+}
+
+
+// Create the deferred hard links of every shard, once all shards are
+// unpacked, recording them in the journal of `config`
+pub fn link_deferred(
+        links: & [DeferredLink], config: & ExtractConfig
+    ) -> Result<(), ArchiverError> {
+    for link in links {
+        create_link(link)?;
+        config.record(link.shard, & link.member)?;
+    }
+    Ok(())
+}
+
+
+// An entry extract would unpack, for a dry run
+pub struct Preview {
+    pub target: PathBuf,
+    // Bytes of data (0 for directories, links and the like)
+    pub size: u64,
+    pub action: Action
+}
+
+
+fn preview_part(
+        tar_path: & str, destination: & str, config: & ExtractConfig,
+        previews: & mut Vec<Preview>
+    ) -> Result<(), ArchiverError> {
+    let codec = Codec::detect(tar_path)?;
+    if codec != Codec::Plain {
+        return Err(ArchiverError::Compressed {
+            path: tar_path.to_string(), codec
+        });
+    }
+    let mut ar = Archive::new(File::open(tar_path)?);
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        if is_shard_index(& mut entry) || (config.flatten && kind.is_dir())
+                || (config.regular_only && is_special(kind)) {
+            continue;
+        }
+        let rel = match destination_path(& entry.path()?, config) {
+            Some(rel) => rel,
+            None => continue
+        };
+        let target = Path::new(destination).join(rel);
+        previews.push(Preview {
+            action: action(& entry, & target, config.policy)?,
+            size: if kind.is_file() { entry.size() } else { 0 },
+            target
+        });
+    }
+    Ok(())
+}
+
+
+// What extracting shard `idx` into `destination` would do with every entry,
+// in the order extract meets them, without writing anything
+pub fn preview_shard(
+        archive_name: & str, names: & NameTemplate, idx: u32,
+        destination: & str, config: ExtractConfig
+    ) -> Result<Vec<Preview>, ArchiverError> {
+    let mut previews = Vec::new();
+    for tar_path in names.parts(archive_name, idx) {
+        preview_part(& tar_path, destination, & config, & mut previews)
+            .map_err(|e| e.in_shard(& tar_path))?;
+    }
+    Ok(previews)
+}
+
+
+// Open a part for reading, refusing compressed ones
+fn open_part(tar_path: & str) -> Result<Archive<File>, ArchiverError> {
+    let codec = Codec::detect(tar_path)?;
+    if codec != Codec::Plain {
+        return Err(ArchiverError::Compressed {
+            path: tar_path.to_string(), codec
+        });
+    }
+    Ok(Archive::new(File::open(tar_path)?))
+}
+
+
+// How many entries of part `tar_path` are archived as `member` (a path
+// normalized by strip_path)
+fn count_member(
+        tar_path: & str, member: & Path
+    ) -> Result<usize, ArchiverError> {
+    let mut count = 0;
+    for entry in open_part(tar_path)?.entries()? {
+        let mut entry = entry?;
+        if ! is_shard_index(& mut entry)
+                && strip_path(& entry.path()?, 0).as_deref() == Some(member) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+
+// The parts of shard `idx` that hold `member`, once for every such entry
+pub fn find_member(
+        archive_name: & str, names: & NameTemplate, idx: u32, member: & Path
+    ) -> Result<Vec<String>, ArchiverError> {
+    let mut found = Vec::new();
+    for tar_path in names.parts(archive_name, idx) {
+        let count = count_member(& tar_path, member)
+            .map_err(|e| e.in_shard(& tar_path))?;
+        found.extend(std::iter::repeat_n(tar_path, count));
+    }
+    Ok(found)
+}
+
+
+// Copy the data of the entry archived as `member` in part `tar_path` to
+// `out`, and return the number of bytes
+pub fn copy_member<W: Write>(
+        tar_path: & str, member: & Path, out: & mut W
+    ) -> Result<u64, ArchiverError> {
+    let mut ar = open_part(tar_path)?;
+    for entry in ar.entries()? {
+        let mut entry = entry?;
+        if is_shard_index(& mut entry)
+                || strip_path(& entry.path()?, 0).as_deref() != Some(member) {
+            continue;
+        }
+        if ! entry.header().entry_type().is_file() {
+            return Err(ArchiverError::InvalidArg(format!(
+                "{} is not a regular file", member.display()
+            )));
+        }
+        return Ok(io::copy(& mut entry, out)?);
+    }
+    Err(ArchiverError::InvalidArg(format!(
+        "no entry {} in {}", member.display(), tar_path
+    )))
+}
+
+
+fn corrupt(path: & str, offset: u64, reason: impl ToString) -> ArchiverError {
+    ArchiverError::Corrupt {
+        path: path.to_string(), offset, reason: reason.to_string()
+    }
+}
+
+
+// Read every entry of shard `idx`, data included, without writing anything,
+// and return how many there are. The first entry that cannot be read fails
+// with the part and the offset of its header.
+pub fn test_shard(
+        archive_name: & str, names: & NameTemplate, idx: u32
+    ) -> Result<u64, ArchiverError> {
+    let mut count = 0;
+    for tar_path in names.parts(archive_name, idx) {
+        let codec = Codec::detect(& tar_path)?;
+        if codec != Codec::Plain {
+            return Err(ArchiverError::Compressed { path: tar_path, codec });
+        }
+        let mut ar = Archive::new(File::open(& tar_path)?);
+        // Where the next header should be
+        let mut offset = 0;
+        for entry in ar.entries()? {
+            let mut entry = entry.map_err(|e| corrupt(& tar_path, offset, e))?;
+            let start = entry.raw_header_position();
+            let size = entry.size();
+            let read = io::copy(& mut entry, & mut io::sink())
+                .map_err(|e| corrupt(& tar_path, start, e))?;
+            if read != size {
+                return Err(corrupt(& tar_path, start, format!(
+                    "entry has {} of {} bytes", read, size
+                )));
+            }
+            let stored = entry.header().entry_size()
+                .map_err(|e| corrupt(& tar_path, start, e))?;
+            offset = entry.raw_file_position() + stored.div_ceil(512) * 512;
+            if ! is_shard_index(& mut entry) {
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
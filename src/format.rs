@@ -0,0 +1,120 @@
+// Tar header formats create can write. They differ in how names that do not
+// fit the 100-byte name field are stored:
+// - gnu (default): in GNU long name entries. Read by GNU tar, bsdtar
+//   (libarchive), Python's tarfile and most other tools, but not by strict
+//   POSIX readers. The only format with sparse entries (see `sparse`).
+// - pax: in PAX extended headers (POSIX.1-2001). Read by GNU tar >= 1.14,
+//   bsdtar, Python's tarfile, 7-Zip and any POSIX reader.
+// - ustar: nowhere; names must fit the ustar name and prefix fields (up to
+//   255 bytes, split at a '/') and link targets 100 bytes, or the item
+//   fails. Read by practically everything, including old or minimal tars.
+use std::fmt;
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    #[default]
+    Gnu,
+    Pax,
+    Ustar
+}
+
+
+impl Format {
+    pub fn name(& self) -> & 'static str {
+        match self {
+            Format::Gnu => "gnu",
+            Format::Pax => "pax",
+            Format::Ustar => "ustar"
+        }
+    }
+
+    pub fn from_name(name: & str) -> Option<Format> {
+        match name {
+            "gnu" => Some(Format::Gnu),
+            "pax" => Some(Format::Pax),
+            "ustar" => Some(Format::Ustar),
+            _ => None
+        }
+    }
+}
+
+
+impl fmt::Display for Format {
+    fn fmt(& self, f: & mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+
+// Sizes of the name fields of a ustar header
+pub const NAME_FIELD: usize = 100;
+const PREFIX_FIELD: usize = 155;
+
+
+// Split `name` into the (prefix, name) fields of a ustar header, at the
+// last '/' that makes both fit. None if there is no such '/'.
+pub fn split_ustar(name: & [u8]) -> Option<(& [u8], & [u8])> {
+    if name.len() <= NAME_FIELD {
+        return Some((& [], name));
+    }
+    let start = name.len() - NAME_FIELD - 1;
+    let end = name.len().min(PREFIX_FIELD + 1);
+    // The '/' itself is in neither field, and the name must not be empty
+    (start..end).rev()
+        .find(|& i| name[i] == b'/' && i + 1 < name.len())
+        .map(|i| (& name[..i], & name[i + 1..]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(prefix: usize, name: usize) -> Vec<u8> {
+        let mut path = vec![b'p'; prefix];
+        path.push(b'/');
+        path.extend(vec![b'n'; name]);
+        path
+    }
+
+    #[test]
+    fn split_ustar_fits_name_field() {
+        let path = vec![b'n'; NAME_FIELD];
+        assert_eq!(split_ustar(& path), Some((& [][..], & path[..])));
+    }
+
+    #[test]
+    fn split_ustar_fills_both_fields() {
+        let path = name(PREFIX_FIELD, NAME_FIELD);
+        let (prefix, rest) = split_ustar(& path).unwrap();
+        assert_eq!(prefix.len(), PREFIX_FIELD);
+        assert_eq!(rest.len(), NAME_FIELD);
+    }
+
+    #[test]
+    fn split_ustar_rejects_long_fields() {
+        assert_eq!(
+            split_ustar(& name(PREFIX_FIELD + 1, NAME_FIELD - 1)), None
+        );
+        assert_eq!(
+            split_ustar(& name(PREFIX_FIELD - 1, NAME_FIELD + 1)), None
+        );
+        assert_eq!(split_ustar(& [b'n'; NAME_FIELD + 1]), None);
+    }
+
+    #[test]
+    fn split_ustar_needs_a_name() {
+        assert_eq!(split_ustar(& name(NAME_FIELD + 1, 0)), None);
+    }
+
+    #[test]
+    fn split_ustar_takes_the_last_slash_that_fits() {
+        let mut path = name(10, 5);
+        path.push(b'/');
+        path.extend(vec![b'm'; NAME_FIELD - 6]);
+        let (prefix, rest) = split_ustar(& path).unwrap();
+        assert_eq!(prefix, & path[..16]);
+        assert_eq!(rest.len(), NAME_FIELD - 6);
+    }
+}
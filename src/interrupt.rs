@@ -0,0 +1,57 @@
+// Ctrl-C handling. The first SIGINT only raises a flag: create workers see it,
+// stop taking new work and finish their shards, so every shard written so far
+// stays a valid Tar file. A second SIGINT aborts immediately (shards that are
+// still open are then left truncated). A deadline (see stop_after) raises
+// the same flag, for runs with a wall-clock limit.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// The flag was raised by the deadline rather than by SIGINT
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+
+pub fn timed_out() -> bool {
+    TIMED_OUT.load(Ordering::SeqCst)
+}
+
+
+// Stop the run once `limit` has passed, as a first Ctrl-C would: files that
+// are being archived are completed, but no further ones are started
+pub fn stop_after(limit: Duration) {
+    thread::spawn(move || {
+        thread::sleep(limit);
+        if ! INTERRUPTED.swap(true, Ordering::SeqCst) {
+            TIMED_OUT.store(true, Ordering::SeqCst);
+        }
+    });
+}
+
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    // Only async-signal-safe operations here: an atomic swap and _exit
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+
+// Install the SIGINT handler. Library users that handle signals themselves
+// should not call this. Does nothing on platforms other than Unix.
+pub fn install_handler() {
+    #[cfg(unix)]
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigint as extern "C" fn(libc::c_int) as usize;
+        libc::sigemptyset(& mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, & action, std::ptr::null_mut());
+    }
+}
@@ -0,0 +1,139 @@
+// Resumable create: every archived item is recorded in a journal, one JSON
+// object per line, once the worker has written it to its shard. A create that
+// finds the journal of an interrupted run skips what it lists and continues
+// the shards where they end. Extract records the files it has written the
+// same way, and skips them when restarted. Items are recorded by the raw
+// bytes of their path: as a "path" string if they are UTF-8, otherwise hex
+// encoded as "path_bytes".
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions, read_to_string, remove_file};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::json::{Value, parse};
+
+
+pub struct Journal {
+    path: String,
+    file: File,
+    // Items an earlier run archived
+    pub archived: HashSet<Vec<u8>>,
+    // The journal existed, so the shards are continued rather than replaced
+    pub resumed: bool
+}
+
+
+impl Journal {
+    // Open the journal at `path` for appending, reading what an earlier run
+    // recorded in it. A last line cut short by a crash is ignored.
+    pub fn open(path: & str) -> io::Result<Journal> {
+        let resumed = Path::new(path).exists();
+        let mut archived = HashSet::new();
+        if resumed {
+            for line in read_to_string(path)?.lines() {
+                if let Some(item) = parse(line).ok().as_ref().and_then(item) {
+                    archived.insert(item);
+                }
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal { path: path.to_string(), file, archived, resumed })
+    }
+
+    // Record that `item` is in `shard`. Only called once the worker has
+    // written the item to the shard file, so the journal never lists an item
+    // the shard does not hold (it may miss the last few, which are then
+    // archived again).
+    pub fn record(& mut self, shard: u32, item: & [u8]) -> io::Result<()> {
+        let path = match std::str::from_utf8(item) {
+            Ok(path) => ("path", Value::str(path)),
+            Err(_) => ("path_bytes", Value::str(& to_hex(item)))
+        };
+        let record = Value::object(vec![
+            ("shard", Value::Int(shard as u64)), path
+        ]);
+        writeln!(self.file, "{}", record)?;
+        self.file.flush()
+    }
+
+    // Whether an earlier run recorded `item` (compared byte by byte)
+    pub fn contains(& self, item: & [u8]) -> bool {
+        self.archived.contains(item)
+    }
+
+    // The run completed: there is nothing left to resume
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        remove_file(& self.path)
+    }
+}
+
+
+// The path bytes of a record
+fn item(record: & Value) -> Option<Vec<u8>> {
+    if let Some(path) = record.get("path").and_then(|p| p.as_str()) {
+        return Some(path.as_bytes().to_vec());
+    }
+    from_hex(record.get("path_bytes")?.as_str()?)
+}
+
+
+fn to_hex(bytes: & [u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+fn from_hex(hex: & str) -> Option<Vec<u8>> {
+    if ! hex.len().is_multiple_of(2)
+            || ! hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn resume_reads_what_was_recorded() {
+        let path = std::env::temp_dir().join(format!(
+            "parallel-tar-journal-test.{}", std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let mut journal = Journal::open(path).unwrap();
+        assert!(! journal.resumed);
+        journal.record(0, b"dir/plain").unwrap();
+        journal.record(1, b"dir/\xff\xfe").unwrap();
+        drop(journal);
+        // A crash in the middle of a record
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        write!(file, "{{\"shard\": 1, \"path\": \"dir/cut").unwrap();
+        drop(file);
+
+        let journal = Journal::open(path).unwrap();
+        assert!(journal.resumed);
+        assert!(journal.contains(b"dir/plain"));
+        assert!(journal.contains(b"dir/\xff\xfe"));
+        // Neither the lossy form of the name, nor the cut record
+        assert!(! journal.contains("dir/\u{fffd}\u{fffd}".as_bytes()));
+        assert!(! journal.contains(b"dir/cut"));
+        assert_eq!(journal.archived.len(), 2);
+        journal.remove().unwrap();
+        assert!(! Path::new(path).exists());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = [0x00, 0x7f, 0x80, 0xff];
+        assert_eq!(to_hex(& bytes), "007f80ff");
+        assert_eq!(from_hex("007f80ff").unwrap(), bytes);
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("+f"), None);
+    }
+}
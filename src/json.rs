@@ -0,0 +1,330 @@
+// Minimal JSON values for plans, manifests and reports. No serialization
+// crate is available to this build, and the documents we emit are simple.
+use std::fmt;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    // Keeps insertion order so output is stable
+    Object(Vec<(String, Value)>)
+}
+
+
+impl Value {
+    pub fn object(fields: Vec<(& str, Value)>) -> Value {
+        Value::Object(
+            fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+        )
+    }
+
+    pub fn str(s: & str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    pub fn get(& self, key: & str) -> Option<& Value> {
+        match self {
+            Value::Object(fields) => {
+                fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None
+        }
+    }
+
+    pub fn as_str(& self) -> Option<& str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_u64(& self) -> Option<u64> {
+        match self {
+            Value::Int(i) => Some(* i),
+            _ => None
+        }
+    }
+
+    pub fn as_array(& self) -> Option<& [Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None
+        }
+    }
+
+    pub fn pretty(& self) -> String {
+        let mut out = String::new();
+        write_value(& mut out, self, Some(0));
+        out
+    }
+}
+
+
+impl fmt::Display for Value {
+    fn fmt(& self, f: & mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        write_value(& mut out, self, None);
+        f.write_str(& out)
+    }
+}
+
+
+fn write_escaped(out: & mut String, s: & str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                out.push_str(& format!("\\u{:04x}", c as u32));
+            }
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+
+fn newline(out: & mut String, indent: Option<usize>) {
+    if let Some(level) = indent {
+        out.push('\n');
+        out.push_str(& "  ".repeat(level));
+    }
+}
+
+
+// `indent` is None for compact output, otherwise the current nesting level
+fn write_value(out: & mut String, value: & Value, indent: Option<usize>) {
+    let inner = indent.map(|l| l + 1);
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if * b { "true" } else { "false" }),
+        Value::Int(i) => out.push_str(& i.to_string()),
+        Value::Float(x) if x.is_finite() => out.push_str(& x.to_string()),
+        Value::Float(_) => out.push_str("null"),
+        Value::String(s) => write_escaped(out, s),
+        Value::Array(items) => {
+            out.push('[');
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                newline(out, inner);
+                write_value(out, item, inner);
+            }
+            if ! items.is_empty() {
+                newline(out, indent);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            out.push('{');
+            for (idx, (key, item)) in fields.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                newline(out, inner);
+                write_escaped(out, key);
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                write_value(out, item, inner);
+            }
+            if ! fields.is_empty() {
+                newline(out, indent);
+            }
+            out.push('}');
+        }
+    }
+}
+
+
+pub fn parse(text: & str) -> Result<Value, String> {
+    let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_ws();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+
+struct Parser<'a> {
+    bytes: & 'a [u8],
+    pos: usize
+}
+
+
+impl Parser<'_> {
+    fn error(& self, msg: & str) -> String {
+        format!("invalid JSON at byte {}: {}", self.pos, msg)
+    }
+
+    fn skip_ws(& mut self) {
+        while self.pos < self.bytes.len()
+                && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(& mut self) -> Option<u8> {
+        self.skip_ws();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(& mut self, c: u8) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(& format!("expected '{}'", c as char)))
+        }
+    }
+
+    fn literal(& mut self, word: & str, value: Value) -> Result<Value, String> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected token"))
+        }
+    }
+
+    fn value(& mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(c) if c == b'-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input"))
+        }
+    }
+
+    fn object(& mut self) -> Result<Value, String> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn array(& mut self) -> Result<Value, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn hex4(& mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn string(& mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let c = * self.bytes.get(self.pos)
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let esc = * self.bytes.get(self.pos)
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    let ch = match esc {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // Surrogate pair
+                            if (0xd800..0xdc00).contains(& code)
+                                    && self.bytes[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        _ => return Err(self.error("invalid escape"))
+                    };
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(ch.encode_utf8(& mut buf).as_bytes());
+                }
+                c => out.push(c)
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn number(& mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self.pos < self.bytes.len()
+                && b"+-.eE0123456789".contains(& self.bytes[self.pos]) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(& self.bytes[start..self.pos]).unwrap();
+        if let Ok(i) = text.parse::<u64>() {
+            return Ok(Value::Int(i));
+        }
+        text.parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| self.error("invalid number"))
+    }
+}
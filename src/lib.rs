@@ -0,0 +1,34 @@
+// Parallel Tar: archive a directory tree into several Tar files ("shards")
+// at once, one worker thread per shard, and extract them again in parallel.
+//
+// `Archiver` is the entry point; the binary is a thin wrapper that turns
+// command line flags into `ArchiveOptions`.
+pub mod archive;
+pub use archive::{
+    ArchiveOptions, Archiver, CreateReport, ShardMetrics, Timing, Utilization,
+    WorkList
+};
+
+pub mod error;
+pub use error::ArchiverError;
+
+pub mod benchmark;
+pub mod codec;
+pub mod dedup;
+pub mod distribution;
+pub mod exclude;
+pub mod extract;
+pub mod format;
+pub mod interrupt;
+pub mod journal;
+pub mod json;
+pub mod manifest;
+pub mod plan;
+pub mod progress;
+pub mod shard;
+pub mod size;
+pub mod sparse;
+pub mod transform;
+pub mod xattrs;
+
+mod sha256;
@@ -1,311 +1,536 @@
-// Multi-threading
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{Sender, Receiver, channel, TryRecvError};
-use std::thread::JoinHandle;
-use std::{thread, time::Duration};
+use std::fs::{File, write};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parallel_tar::{ArchiveOptions, Archiver, ArchiverError, CreateReport};
+use parallel_tar::benchmark::Scratch;
+use parallel_tar::interrupt;
+use parallel_tar::distribution::Distribution;
+use parallel_tar::extract::Action;
+use parallel_tar::manifest::{Fragment, Manifest};
+use parallel_tar::plan::Plan;
+use parallel_tar::progress::{json_progress, print_progress};
+use parallel_tar::shard::STDOUT;
+use parallel_tar::size::{format_count, format_size, format_throughput};
+
+// Command line
+mod cli;
+use cli::{Mode, Options};
+
+
+// Set by --json-errors: fatal errors are printed as one JSON object
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+
+// Print a fatal error and exit. Misuse exits with 2, as clap does for bad
+// command lines, and everything else with 1.
+fn fail(e: ArchiverError) -> ! {
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", e.to_json());
+    } else {
+        eprintln!("Error: {}", e);
+    }
+    exit(if matches!(e, ArchiverError::InvalidArg(_)) { 2 } else { 1 });
+}
 
-// Tar files
-use std::fs::{File, symlink_metadata, read_link};
-use std::os::unix::fs::PermissionsExt; // Import for Unix-specific permissions
-use std::path::Path;
-use tar::{Builder, Header, EntryType, Archive};
-use walkdir::WalkDir;
-use std::error::Error;
 
+fn or_exit<T>(result: Result<T, ArchiverError>) -> T {
+    result.unwrap_or_else(|e| fail(e))
+}
 
-// Clap
-use clap::{Arg, Command};
 
+// Items that could not be archived: one line per item with --verbose,
+// otherwise one per group of items that failed the same way
+fn print_errors(opts: & Options, report: & CreateReport) {
+    if opts.verbose {
+        for (path, error) in & report.failed {
+            eprintln!(
+                "Warning: could not archive {}: {}", path.display(), error
+            );
+        }
+        for (_, error) in & report.unreadable {
+            eprintln!("Warning: {}", error);
+        }
+        return;
+    }
+    for group in report.error_groups() {
+        if group.count == 1 {
+            eprintln!(
+                "Warning: could not archive {}: {}",
+                group.example.display(), group.error
+            );
+        } else {
+            eprintln!(
+                "Warning: {} files could not be archived: {} under {} \
+                (e.g. {}: {}; see --verbose)",
+                format_count(group.count as u64), group.cause,
+                group.under.display(), group.example.display(), group.error
+            );
+        }
+    }
+}
 
-fn find_files(
-        folder_path: & str, follow_links: bool
-    ) -> Result<Vec<String>, Box<dyn Error>> {
 
-    let mut files: Vec<String> = Vec::new();
-    for entry in WalkDir::new(folder_path).follow_links(follow_links) {
-        let entry = entry?;
-        let path = entry.path();
+// Per-shard breakdown (and with --list-threads a summary), to spot imbalance
+// between the shards, and the overall throughput. Goes to stderr when the
+// archive itself is written to stdout.
+fn print_metrics(opts: & Options, report: & CreateReport) {
+    let mut lines: Vec<String> = report.shards.iter()
+        .map(|shard| shard.to_string())
+        .collect();
+    if opts.list_threads {
+        lines.push(report.utilization().to_string());
+    }
+    lines.push(format!("Archived {}", format_throughput(
+        report.processed.len() as u64, report.bytes(), report.elapsed
+    )));
+    for line in lines {
+        if opts.archive_name == STDOUT {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
 
-        files.push(path.to_str().unwrap().to_string());
+// Exit with the conventional status for SIGINT if Ctrl-C stopped the run,
+// or with that of timeout(1) if --max-runtime did
+fn check_interrupted(report: & CreateReport) {
+    if report.interrupted && interrupt::timed_out() {
+        eprintln!(
+            "Stopped by --max-runtime: {} files were archived; the shards \
+            are complete but partial",
+            report.processed.len()
+        );
+        exit(124);
     }
+    if report.interrupted {
+        eprintln!(
+            "Interrupted: {} files were archived; the shards are complete \
+            but partial",
+            report.processed.len()
+        );
+        exit(130);
+    }
+}
 
-    Ok(files)
+
+// A descriptor the caller opened for us (e.g. `3>progress.ndjson`)
+#[cfg(unix)]
+fn open_fd(fd: i32) -> File {
+    use std::os::unix::io::FromRawFd;
+    // Those belong to io::stdin, io::stdout and io::stderr
+    if fd <= 2 {
+        fail(ArchiverError::InvalidArg(format!(
+            "--progress-fd {} is stdin, stdout or stderr (use 3 or above)", fd
+        )));
+    }
+    // The File owns (and closes) a duplicate, never the caller's descriptor,
+    // so nothing else in the process that holds `fd` is affected
+    let dup = unsafe { libc::dup(fd) };
+    if dup < 0 {
+        let e = io::Error::last_os_error();
+        fail(ArchiverError::Io(io::Error::new(
+            e.kind(), format!("cannot use --progress-fd {}: {}", fd, e)
+        )));
+    }
+    unsafe { File::from_raw_fd(dup) }
 }
 
 
-fn set_mutex<T: Copy>(mutex: & Arc<Mutex<T>>, val: T) {
-    let mut lock = mutex.lock().unwrap();
-    * lock = val;
-    drop(lock);
+#[cfg(not(unix))]
+fn open_fd(_: i32) -> File {
+    fail(ArchiverError::InvalidArg(
+        "--progress-fd is only supported on Unix".to_string()
+    ));
 }
 
 
-fn get_mutex<T: Copy>(mutex: & Arc<Mutex<T>>) -> T {
-    let lock = mutex.lock().unwrap();
-    let val = * lock;
-    drop(lock);
-    return val;
+// Where --progress-fd or --progress-file sends JSON progress records
+fn progress_sink(opts: & Options) -> Option<File> {
+    if let Some(fd) = opts.progress_fd {
+        return Some(open_fd(fd));
+    }
+    let path = opts.progress_file.as_ref()?;
+    Some(File::create(path).unwrap_or_else(|e| {
+        fail(ArchiverError::Io(io::Error::new(
+            e.kind(), format!("cannot write progress to {}: {}", path, e)
+        )))
+    }))
 }
 
 
-fn take_mutex_try_many<T>(
-        rx: & Arc<Mutex<Receiver<T>>>,
-        max_try: u32, wait: Duration,
-        completed: & Arc<Mutex<bool>>
-    ) -> Result<T, TryRecvError> {
-
-    let mut ct = 0;
-    loop {
-        // Grab lock the the guard mutex, and take data from channel
-        let data = rx.lock().unwrap();
-        let datum = data.try_recv();
-        drop(data);
-        match datum {
-            Ok(input) => {
-                return Ok(input);
-            }
-            Err(error) => {
-                if (ct > max_try) || get_mutex(& completed) {
-                    return Err(error);
-                }
-                ct += 1;
-                thread::sleep(wait);
+fn archive_options(opts: & Options, target: & str) -> ArchiveOptions {
+    let quiet = opts.quiet;
+    let options = match progress_sink(opts).map(json_progress) {
+        Some(json) => ArchiveOptions::new(target).progress(move |p| {
+            if ! quiet {
+                print_progress(p);
             }
-        }
+            json(p);
+        }),
+        None if quiet => ArchiveOptions::new(target),
+        None => ArchiveOptions::new(target).progress(print_progress)
+    };
+    let options = options
+        .more_targets(opts.targets.get(1..).unwrap_or_default())
+        .num_threads(opts.num_threads)
+        .follow_links(opts.follow_links)
+        .follow_toplevel(opts.follow_toplevel)
+        .one_file_system(opts.one_file_system)
+        .check_case(opts.check_case)
+        .excludes(& opts.excludes)
+        .exclude_vcs(opts.exclude_vcs)
+        .exclude_larger_than(opts.exclude_larger_than)
+        .max_file_size(opts.max_file_size)
+        .exclude_smaller_than(opts.exclude_smaller_than)
+        .deterministic(opts.deterministic)
+        .format(opts.format)
+        .group_dirs(opts.group_dirs)
+        .stable_placement(opts.stable_placement)
+        .limits(opts.limits)
+        .checksums(opts.checksums)
+        .append(opts.append)
+        .timing(opts.timing)
+        .ignore_errors(opts.ignore_errors)
+        .fail_fast(opts.fail_fast)
+        .skip_special(opts.skip_special)
+        .dedup(opts.dedup)
+        .journal(opts.journal.as_deref())
+        .preserve_hardlinks(opts.preserve_hardlinks)
+        .fsync(opts.fsync)
+        .shard_index(opts.shard_index)
+        .absolute_names(opts.absolute_names)
+        .xattrs(opts.xattrs)
+        .sparse(opts.sparse)
+        .check_symlinks(opts.check_symlinks)
+        .verbose(opts.verbose)
+        .name_template(opts.name_template.clone())
+        .shard_dirs(& opts.shard_dirs)
+        .files_from(opts.files_from.as_deref())
+        .on_conflict(opts.on_conflict)
+        .strip_components(opts.strip_components)
+        .subdir(opts.subdir.clone())
+        .flatten(opts.flatten)
+        .regular_only(opts.regular_only)
+        .dereference_hardlinks(opts.dereference_hardlinks)
+        .same_permissions(! opts.no_same_permissions)
+        .strip_root(opts.strip_root)
+        .relative_to(opts.relative_to.clone())
+        .check_space(opts.check_space)
+        .parallel_entries(opts.parallel_entries);
+    match & opts.transform {
+        Some(transform) => options.transform(Box::new(transform.clone())),
+        None => options
     }
 }
 
 
-fn collect_expected<T>(ct_expect: usize, rx: Receiver<T>, wait: Duration) -> Vec<T> {
-    let mut items: Vec<T> = Vec::new();
-    // Non-blocking (but patient) data collection
-    let mut ct_recv = 0;
-    loop {
-        if ct_recv >= ct_expect {
-            break;
+fn create(opts: & Options) -> CreateReport {
+    match & opts.from_plan {
+        Some(plan_path) => {
+            let archiver = Archiver::new(
+                & opts.archive_name, archive_options(opts, "")
+            );
+            or_exit(archiver.create_from_plan(plan_path, opts.shard.unwrap()))
         }
-        match rx.recv_timeout(wait) {
-            Ok(result) => {
-                items.push(result);
-                ct_recv +=1 ;
-            }
-            Err(error) => {
-                panic!(
-                    "Failure {} while collecting {} out of {}",
-                    error, ct_recv, ct_expect
-                );
-            }
+        None => {
+            let archiver = Archiver::new(
+                & opts.archive_name, archive_options(opts, target(opts))
+            );
+            or_exit(archiver.create())
         }
     }
-    return items;
 }
 
 
-fn is_symlink(path_str: & str) -> bool {
-    let path = Path::new(& path_str);
-    path.symlink_metadata().map(
-        |metadata| metadata.file_type().is_symlink()
-    ).unwrap_or(false)
+// Archive a generated tree of `count` files and report the throughput
+fn benchmark(opts: & Options, count: u64) {
+    let parent = opts.targets.first()
+        .map_or_else(std::env::temp_dir, PathBuf::from);
+    let scratch = or_exit(
+        Scratch::generate(& parent, count, opts.benchmark_size)
+            .map_err(ArchiverError::Io)
+    );
+    println!(
+        "Generated {} files ({}) in {}",
+        format_count(scratch.files), format_size(scratch.bytes),
+        scratch.tree().display()
+    );
+    let archiver = Archiver::new(
+        & scratch.archive_name().to_string_lossy(),
+        archive_options(opts, & scratch.tree().to_string_lossy())
+    );
+    let result = archiver.create();
+    scratch.remove();
+    let report = or_exit(result);
+    print_metrics(opts, & report);
+    check_interrupted(& report);
+}
+
+
+// No TARGET with --files-from
+fn target(opts: & Options) -> & str {
+    opts.targets.first().map_or("", |t| t.as_str())
 }
 
 
-fn create_worker_thread(
-        output_tar_path: & str,
-        rx: Arc<Mutex<Receiver<String>>>,
-        tx: Sender<String>,
-        completed: Arc<Mutex<bool>>
-    ) {
-
-    let output_file = File::create(output_tar_path).unwrap();
-    let mut archive = Builder::new(output_file);
-
-    loop {
-        match take_mutex_try_many(& rx, 100, Duration::from_millis(128), & completed) {
-            Ok(input) => {
-                if is_symlink(& input) {
-                    let mut header = Header::new_gnu();
-                    header.set_entry_type(EntryType::Symlink);
-                    header.set_size(0);
-                    header.set_mode(
-                        symlink_metadata(& input).unwrap().permissions().mode()
-                    );
-
-                    let link_target = read_link(& input).unwrap();
-                    let _ = header.set_link_name(& link_target);
-                    archive.append_link(&mut header, & input, & link_target).unwrap();
-                } else {
-                    archive.append_path(input.clone()).unwrap();
-                }
-                // Used to check work that has been done
-                tx.send(input).unwrap();
-            }
-            Err(error) => {
-                // Check if work is done
-                if get_mutex(& completed) {
-                    return;
-                }
-
-                panic!(
-                    "Failure {} on thread responsible for: {}",
-                    error, output_tar_path
-                );
+// List what create would archive, without writing anything
+fn dry_run(opts: & Options) {
+    let archiver = Archiver::new(
+        & opts.archive_name, archive_options(opts, target(opts))
+    );
+    let list = or_exit(archiver.enumerate());
+    for (_, error) in & list.unreadable {
+        eprintln!("Warning: {}", error);
+    }
+    if list.excluded_files > 0 {
+        eprintln!(
+            "Excluded {} files ({}) by size",
+            format_count(list.excluded_files), format_size(list.excluded_bytes)
+        );
+    }
+    // Names as they are (not necessarily UTF-8), so that the list can be
+    // fed back with --files-from
+    let mut out = io::stdout().lock();
+    let listed = list.items.iter().try_for_each(|path| {
+        out.write_all(path.as_os_str().as_encoded_bytes())?;
+        out.write_all(b"\n")
+    }).and_then(|_| out.flush());
+    match listed {
+        // The reader (e.g. `head`) has seen enough
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        listed => or_exit(listed.map_err(ArchiverError::Io))
+    }
+}
+
+
+// List what extract would write where, without writing anything
+fn extract_dry_run(opts: & Options) {
+    let archiver = Archiver::new(
+        & opts.archive_name, archive_options(opts, target(opts))
+    );
+    let previews = or_exit(archiver.preview_extract());
+    let mut written = 0;
+    let mut bytes = 0;
+    let mut skipped = 0;
+    let mut conflicts = 0;
+    for preview in & previews {
+        println!(
+            "{:<9} {:>10}  {}", preview.action.name(),
+            format_size(preview.size), preview.target.display()
+        );
+        match preview.action {
+            Action::Create | Action::Overwrite => {
+                written += 1;
+                bytes += preview.size;
             }
+            Action::Skip => skipped += 1,
+            Action::Conflict => conflicts += 1
         }
     }
+    println!(
+        "Would write {} entries ({}), skipping {}",
+        format_count(written), format_size(bytes), format_count(skipped)
+    );
+    if conflicts > 0 {
+        eprintln!(
+            "{} entries conflict with existing files: extract would stop at \
+            the first",
+            format_count(conflicts)
+        );
+    }
 }
 
 
-fn extract_worker_thread(tar_path: & str, destination: & str) {
-    let mut ar = Archive::new(File::open(tar_path).unwrap());
-    ar.unpack(destination).unwrap();
+// Check that every shard reads through as Tar (exit status 1 if one does not)
+fn test(opts: & Options) {
+    let archiver = Archiver::new(
+        & opts.archive_name, archive_options(opts, target(opts))
+    );
+    let entries = or_exit(archiver.test());
+    println!(
+        "{}: {} shards, {} entries, no errors",
+        opts.archive_name, archiver.num_shards(), entries
+    );
 }
 
 
-fn create(
-        archive_name: & String, target: & String,
-        num_threads: & u32, follow_links: & bool
-    ) {
-    // Create channels for sending work and receiving results
-    let (tx_work, rx_work) = channel();
-    let (tx_results, rx_results) = channel();
-    let shared_work = Arc::new(Mutex::new(rx_work));
-    // Used to signal threads to shut down (once work is complete)
-    let work_completed = Arc::new(Mutex::new(false));
-
-    // Spawn worker threads
-    println!("Starting {} worker threads", num_threads);
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
-    for idx in 0..*num_threads {
-        let rx = Arc::clone(& shared_work);
-        let tx = tx_results.clone();
-        let cmp = Arc::clone(& work_completed);
-        let name = format!("{}.{}.tar", archive_name, idx);
-        handles.push(
-            thread::spawn(move || {
-                create_worker_thread(name.as_str(), rx, tx, cmp);
-            })
-        );
-    }
+// Write the data of the entry given with --only to stdout, and nothing else
+fn extract_to_stdout(opts: & Options) {
+    let archiver = Archiver::new(
+        & opts.archive_name, archive_options(opts, target(opts))
+    );
+    let member = opts.only.as_deref().unwrap();
+    let mut stdout = io::stdout().lock();
+    or_exit(archiver.extract_member(member, & mut stdout));
+    or_exit(stdout.flush().map_err(ArchiverError::Io));
+}
 
-    println!("Enumerating files. Following links? {}", follow_links);
-    let work_items = find_files(target, *follow_links).unwrap();
-    // Add work to the work channel
-    for work_item in & work_items {
-        tx_work.send(work_item.to_string()).unwrap();
-    }
 
-    println!("Collecting worker status (workers are working) ...");
-    let processed_items = collect_expected(
-        work_items.len(), rx_results, Duration::from_millis(4000)
+// Write `contents` to the file at `path`, exiting with an error naming it
+// if that fails
+fn write_output(path: & str, contents: String) {
+    or_exit(write(path, contents).map_err(|error| ArchiverError::Io(
+        io::Error::new(
+            error.kind(), format!("cannot write {}: {}", path, error)
+        )
+    )));
+}
+
+
+fn save_plan(plan: & Plan, path: & str) {
+    write_output(path, plan.to_json().pretty() + "\n");
+    eprintln!(
+        "Wrote plan for {} files in {} shards to {}",
+        plan.shards.iter().map(|s| s.len()).sum::<usize>(), plan.shards.len(),
+        path
     );
-    set_mutex(& work_completed, true);
+}
 
-    println!(" ... waiting for workers to finish ...");
-    for h in handles {
-        h.join().unwrap();
-    }
-    println!(" ... workers are done ...");
-    drop(tx_work);
 
-    println!("... checking worker status.");
-    for i in &processed_items {
-        if ! work_items.iter().any(|e| e == i ) {
-            println!("Work item {} requested but not processed!", i)
-        }
+// Write the plan for `opts.targets[0]` as JSON, to `opts.output` or stdout
+fn plan(opts: & Options) {
+    let archiver = Archiver::new("", archive_options(opts, & opts.targets[0]));
+    let plan = or_exit(archiver.plan());
+    match & opts.output {
+        Some(path) => save_plan(& plan, path),
+        None => print!("{}", plan.to_json().pretty() + "\n")
     }
 }
 
 
-fn extract(
-        archive_name: & String, target: & String, num_threads: & u32
-    ) {
-
-    // Spawn worker threads
-    println!("Starting {} worker threads", num_threads);
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
-    for idx in 0..*num_threads {
-        let name = format!("{}.{}.tar", archive_name, idx);
-        let ctarget = target.clone();
-        handles.push(
-            thread::spawn(move || {
-                extract_worker_thread(name.as_str(), ctarget.as_str());
-            })
-        );
+// Check that the files in a saved plan still exist with their planned sizes,
+// so single-shard jobs are not started from a stale plan. With `regenerate`
+// a stale plan is recomputed (using the current `excludes`, which the plan
+// does not record) and written to `output`, or over `plan_path`.
+fn verify_plan(opts: & Options, plan_path: & str) {
+    let plan = or_exit(Plan::load(plan_path).map_err(ArchiverError::Plan));
+    let drift = plan.verify();
+    if drift.is_empty() {
+        eprintln!("Plan {} matches the filesystem", plan_path);
+        return;
     }
 
-    println!(" ... waiting for workers to finish ...");
-    for h in handles {
-        h.join().unwrap();
+    for d in & drift {
+        eprintln!("{}", d);
+    }
+    eprintln!("Plan {} is stale: {} files changed", plan_path, drift.len());
+    if ! opts.regenerate {
+        exit(1);
+    }
+
+    let options = archive_options(opts, & plan.target)
+        .num_threads(plan.shards.len() as u32)
+        .group_dirs(plan.distribution == Distribution::ParentHash);
+    let fresh = or_exit(Archiver::new("", options).plan());
+    save_plan(& fresh, opts.output.as_deref().unwrap_or(plan_path));
+}
+
+
+// Combine the manifest fragments written by `--from-plan --shard` runs into
+// one manifest, refusing if shards are missing, duplicated or overlap.
+fn merge_manifests(fragment_paths: & [String], output: Option<& str>) {
+    let mut fragments: Vec<Fragment> = Vec::new();
+    for path in fragment_paths {
+        fragments.push(or_exit(Fragment::load(path).map_err(ArchiverError::Plan)));
     }
-    println!(" ... workers are done.");
 
+    let manifest = match Manifest::merge(fragments) {
+        Ok(manifest) => manifest,
+        Err(problems) => fail(ArchiverError::Plan(
+            format!("cannot merge manifest fragments:\n{}", problems)
+        ))
+    };
+    let text = manifest.to_json().pretty() + "\n";
+
+    match output {
+        Some(path) => {
+            write_output(path, text);
+            eprintln!(
+                "Merged {} fragments into {}", fragment_paths.len(), path
+            );
+        }
+        None => print!("{}", text)
+    }
 }
 
 
 fn main() {
-    let args = Command::new("Parallel Tar")
-        .version("1.0")
-        .author("Johannes Blaschke")
-        .about("Add target directory to parallel list of Tar archives.")
-        .arg(
-            Arg::new("target")
-            .value_name("TARGET")
-            .help("Target for compression/decompression")
-            .required(true)
-            .index(1)
-        )
-        .arg(
-            Arg::new("create")
-            .short('c')
-            .long("create")
-            .help("Create an archive")
-            .required_unless_present("extract")
-            .num_args(0)
-        )
-        .arg(
-            Arg::new("extract")
-            .short('x')
-            .long("extract")
-            .help("Extract a list of archives")
-            .required_unless_present("create")
-            .num_args(0)
-        )
-        .arg(
-            Arg::new("follow_links")
-            .short('l')
-            .long("follow")
-            .help("Follow links while enumerating files")
-            .required(false)
-            .num_args(0)
-        )
-        .arg(
-            Arg::new("archive_name")
-            .short('f')
-            .long("file")
-            .help("Name of the Tar archive")
-            .required(true)
-            .num_args(1)
-        )
-        .arg(
-            Arg::new("num_threads")
-            .short('n')
-            .help("Number of parallel threads to use")
-            .required(true)
-            .num_args(1)
-            .value_parser(clap::value_parser!(u32))
-        )
-        .get_matches();
-
-    let target = args.get_one::<String>("target").unwrap();
-    let archive_name = args.get_one::<String>("archive_name").unwrap();
-    let num_threads = args.get_one::<u32>("num_threads").unwrap();
-    let create_mode = args.get_one::<bool>("create").unwrap();
-    let extract_mode = args.get_one::<bool>("extract").unwrap();
-    let follow_links = args.get_one::<bool>("follow_links").unwrap();
-
-    if * create_mode {
-        create(archive_name, target, num_threads, follow_links);
-    } else if * extract_mode {
-        extract(archive_name, target, num_threads);
+    let opts = Options::parse();
+    JSON_ERRORS.store(opts.json_errors, Ordering::Relaxed);
+
+    match opts.mode {
+        Mode::Create if opts.dry_run => dry_run(& opts),
+        Mode::Create if opts.benchmark.is_some() => {
+            interrupt::install_handler();
+            benchmark(& opts, opts.benchmark.unwrap());
+        }
+        Mode::Create => {
+            interrupt::install_handler();
+            if let Some(limit) = opts.max_runtime {
+                interrupt::stop_after(limit);
+            }
+            let report = create(& opts);
+            print_errors(& opts, & report);
+            for path in & report.skipped {
+                eprintln!(
+                    "Warning: skipped special file {}", path.display()
+                );
+            }
+            for path in & report.oversized {
+                eprintln!(
+                    "Warning: skipped {}: larger than --max-file-size",
+                    path.display()
+                );
+            }
+            print_metrics(& opts, & report);
+            check_interrupted(& report);
+        }
+        Mode::Extract if opts.test => test(& opts),
+        Mode::Extract if opts.to_stdout => extract_to_stdout(& opts),
+        Mode::Extract if opts.dry_run => extract_dry_run(& opts),
+        Mode::Extract => {
+            let archiver = Archiver::new(
+                & opts.archive_name, archive_options(& opts, & opts.targets[0])
+            );
+            let report = or_exit(archiver.extract());
+            println!("Extracted {}", format_throughput(
+                report.entries, report.bytes, report.elapsed
+            ));
+            if report.skipped > 0 || report.overwritten > 0 {
+                println!(
+                    "{} existing files skipped, {} overwritten",
+                    report.skipped, report.overwritten
+                );
+            }
+            if report.special > 0 {
+                println!(
+                    "{} special entries (devices, FIFOs, symlinks) skipped",
+                    report.special
+                );
+            }
+            if report.dereferenced > 0 {
+                println!(
+                    "{} hard links extracted as copies", report.dereferenced
+                );
+            }
+        }
+        Mode::Plan => match & opts.verify {
+            Some(plan_path) => verify_plan(& opts, plan_path),
+            None => plan(& opts)
+        },
+        Mode::MergeManifests => merge_manifests(
+            & opts.targets, opts.output.as_deref()
+        ),
+        Mode::ContentDigest => {
+            let archiver = Archiver::new(
+                "", archive_options(& opts, & opts.targets[0])
+            );
+            println!("{}", or_exit(archiver.content_digest()));
+        }
     }
 }
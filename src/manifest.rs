@@ -0,0 +1,162 @@
+// Manifest fragments record which files a single-shard create (one process
+// running `--from-plan --shard IDX`) actually archived. Merging the
+// fragments of all shards yields the manifest of the whole archive set.
+use std::collections::HashMap;
+use std::fs::{read_to_string, write};
+use std::io;
+
+use crate::json::{Value, parse};
+
+
+pub fn fragment_path(archive_name: & str, shard: u32) -> String {
+    format!("{}.{}.manifest.json", archive_name, shard)
+}
+
+
+pub struct Fragment {
+    pub shard: u32,
+    pub num_shards: u32,
+    // Number of files the plan assigned to this shard
+    pub planned: u64,
+    pub files: Vec<String>
+}
+
+
+fn field<'a>(value: & 'a Value, key: & str) -> Result<& 'a Value, String> {
+    value.get(key).ok_or(format!("missing '{}'", key))
+}
+
+
+fn field_u32(value: & Value, key: & str) -> Result<u32, String> {
+    field(value, key)?.as_u64()
+        .and_then(|v| u32::try_from(v).ok())
+        .ok_or(format!("'{}' must be a shard number", key))
+}
+
+
+fn string_list(values: & [String]) -> Value {
+    Value::Array(values.iter().map(|f| Value::str(f)).collect())
+}
+
+
+impl Fragment {
+    pub fn to_json(& self) -> Value {
+        Value::object(vec![
+            ("shard", Value::Int(self.shard as u64)),
+            ("num_shards", Value::Int(self.num_shards as u64)),
+            ("planned", Value::Int(self.planned)),
+            ("files", string_list(& self.files))
+        ])
+    }
+
+    pub fn from_json(json: & Value) -> Result<Fragment, String> {
+        let mut files = Vec::new();
+        for file in field(json, "files")?.as_array()
+                .ok_or("'files' must be an array")? {
+            files.push(
+                file.as_str().ok_or("'files' must hold strings")?.to_string()
+            );
+        }
+        Ok(Fragment {
+            shard: field_u32(json, "shard")?,
+            num_shards: field_u32(json, "num_shards")?,
+            planned: field(json, "planned")?.as_u64()
+                .ok_or("'planned' must be an integer")?,
+            files
+        })
+    }
+
+    pub fn save(& self, path: & str) -> io::Result<()> {
+        write(path, self.to_json().pretty() + "\n")
+    }
+
+    pub fn load(path: & str) -> Result<Fragment, String> {
+        let text = read_to_string(path)
+            .map_err(|e| format!("cannot read fragment {}: {}", path, e))?;
+        Fragment::from_json(& parse(& text)?)
+            .map_err(|e| format!("bad fragment {}: {}", path, e))
+    }
+}
+
+
+pub struct Manifest {
+    // Files archived in each shard, indexed by shard number
+    pub shards: Vec<Vec<String>>
+}
+
+
+impl Manifest {
+    // Combine the fragments of one distributed create. Fails, listing every
+    // problem found, if a shard is missing or duplicated, a shard archived
+    // fewer files than planned, or a file shows up in more than one shard.
+    pub fn merge(fragments: Vec<Fragment>) -> Result<Manifest, String> {
+        let mut problems: Vec<String> = Vec::new();
+        let num_shards = match fragments.first() {
+            Some(f) => f.num_shards,
+            None => return Err("no manifest fragments given".to_string())
+        };
+
+        let mut shards: Vec<Option<Vec<String>>> = Vec::new();
+        shards.resize_with(num_shards as usize, || None);
+        let mut owner: HashMap<String, u32> = HashMap::new();
+        for fragment in fragments {
+            if fragment.num_shards != num_shards {
+                problems.push(format!(
+                    "shard {} belongs to a plan with {} shards, expected {}",
+                    fragment.shard, fragment.num_shards, num_shards
+                ));
+                continue;
+            }
+            if fragment.shard >= num_shards {
+                problems.push(format!("shard {} is out of range", fragment.shard));
+                continue;
+            }
+            if shards[fragment.shard as usize].is_some() {
+                problems.push(format!("shard {} given more than once", fragment.shard));
+                continue;
+            }
+            if (fragment.files.len() as u64) < fragment.planned {
+                problems.push(format!(
+                    "shard {} archived {} of {} planned files",
+                    fragment.shard, fragment.files.len(), fragment.planned
+                ));
+            }
+            for file in & fragment.files {
+                if let Some(other) = owner.insert(file.clone(), fragment.shard) {
+                    problems.push(format!(
+                        "{} is in both shard {} and shard {}",
+                        file, other, fragment.shard
+                    ));
+                }
+            }
+            shards[fragment.shard as usize] = Some(fragment.files);
+        }
+
+        for (idx, shard) in shards.iter().enumerate() {
+            if shard.is_none() {
+                problems.push(format!("shard {} is missing", idx));
+            }
+        }
+        if ! problems.is_empty() {
+            return Err(problems.join("\n"));
+        }
+
+        Ok(Manifest {
+            shards: shards.into_iter().map(|s| s.unwrap()).collect()
+        })
+    }
+
+    pub fn to_json(& self) -> Value {
+        Value::object(vec![
+            ("num_shards", Value::Int(self.shards.len() as u64)),
+            ("shards", Value::Array(
+                self.shards.iter().enumerate().map(|(idx, files)| {
+                    Value::object(vec![
+                        ("index", Value::Int(idx as u64)),
+                        ("files", string_list(files))
+                    ])
+                }).collect()
+            ))
+        ])
+    }
+}
@@ -0,0 +1,165 @@
+// Enumeration plans: the shard assignment for every file, computed up front
+// so that external schedulers (e.g. one array task per shard) can reproduce
+// exactly the placement create would use.
+use std::fmt;
+use std::fs::{read_to_string, symlink_metadata};
+use std::path::{Path, PathBuf};
+
+use crate::distribution::Distribution;
+use crate::json::{Value, parse};
+
+
+pub struct PlannedFile {
+    pub path: String,
+    pub size: u64
+}
+
+
+pub struct Plan {
+    pub target: String,
+    pub distribution: Distribution,
+    pub shards: Vec<Vec<PlannedFile>>
+}
+
+
+// A planned file that no longer matches the filesystem
+pub enum Drift {
+    Missing(String),
+    Resized { path: String, planned: u64, actual: u64 }
+}
+
+
+impl fmt::Display for Drift {
+    fn fmt(& self, f: & mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Drift::Missing(path) => write!(f, "missing: {}", path),
+            Drift::Resized { path, planned, actual } => write!(
+                f, "resized: {} ({} bytes planned, {} now)",
+                path, planned, actual
+            )
+        }
+    }
+}
+
+
+fn field<'a>(value: & 'a Value, key: & str) -> Result<& 'a Value, String> {
+    value.get(key).ok_or(format!("missing '{}'", key))
+}
+
+
+fn entry_size(path: & Path) -> u64 {
+    match symlink_metadata(path) {
+        Ok(meta) if meta.is_file() => meta.len(),
+        _ => 0
+    }
+}
+
+
+impl Plan {
+    // `work_items` must already be sorted for the plan to be deterministic.
+    // Plans are JSON, so paths that are not UTF-8 are stored lossily (and
+    // then fail as missing when the plan is archived).
+    pub fn build(
+            target: & str, work_items: & [PathBuf],
+            num_shards: u32, distribution: Distribution
+        ) -> Plan {
+
+        let mut shards: Vec<Vec<PlannedFile>> = Vec::new();
+        shards.resize_with(num_shards as usize, Vec::new);
+        for item in work_items {
+            let shard = distribution.shard_of(item, num_shards);
+            shards[shard].push(PlannedFile {
+                path: item.to_string_lossy().to_string(),
+                size: entry_size(item)
+            });
+        }
+
+        Plan {
+            target: target.to_string(),
+            distribution,
+            shards
+        }
+    }
+
+    pub fn load(path: & str) -> Result<Plan, String> {
+        let text = read_to_string(path)
+            .map_err(|e| format!("cannot read plan {}: {}", path, e))?;
+        let json = parse(& text)?;
+        Plan::from_json(& json).map_err(|e| format!("bad plan {}: {}", path, e))
+    }
+
+    pub fn from_json(json: & Value) -> Result<Plan, String> {
+        let target = field(json, "target")?.as_str()
+            .ok_or("'target' must be a string")?.to_string();
+        let distribution = field(json, "distribution")?.as_str()
+            .and_then(Distribution::from_name)
+            .ok_or("unknown 'distribution'")?;
+
+        let mut shards = Vec::new();
+        for (idx, shard) in field(json, "shards")?.as_array()
+                .ok_or("'shards' must be an array")?.iter().enumerate() {
+            if field(shard, "index")?.as_u64() != Some(idx as u64) {
+                return Err(format!("shard {} is out of order", idx));
+            }
+            let mut files = Vec::new();
+            for file in field(shard, "files")?.as_array()
+                    .ok_or("'files' must be an array")? {
+                files.push(PlannedFile {
+                    path: field(file, "path")?.as_str()
+                        .ok_or("'path' must be a string")?.to_string(),
+                    size: field(file, "size")?.as_u64()
+                        .ok_or("'size' must be an integer")?
+                });
+            }
+            shards.push(files);
+        }
+
+        Ok(Plan {
+            target,
+            distribution,
+            shards
+        })
+    }
+
+    // Re-stat every planned file. New files under the target are not
+    // detected; only what the plan would archive is checked.
+    pub fn verify(& self) -> Vec<Drift> {
+        let mut drift = Vec::new();
+        for file in self.shards.iter().flatten() {
+            if symlink_metadata(& file.path).is_err() {
+                drift.push(Drift::Missing(file.path.clone()));
+                continue;
+            }
+            let actual = entry_size(Path::new(& file.path));
+            if actual != file.size {
+                drift.push(Drift::Resized {
+                    path: file.path.clone(), planned: file.size, actual
+                });
+            }
+        }
+        drift
+    }
+
+    pub fn to_json(& self) -> Value {
+        let shards = self.shards.iter().enumerate().map(|(idx, files)| {
+            let bytes: u64 = files.iter().map(|f| f.size).sum();
+            Value::object(vec![
+                ("index", Value::Int(idx as u64)),
+                ("bytes", Value::Int(bytes)),
+                ("files", Value::Array(
+                    files.iter().map(|f| Value::object(vec![
+                        ("path", Value::str(& f.path)),
+                        ("size", Value::Int(f.size))
+                    ])).collect()
+                ))
+            ])
+        }).collect();
+
+        Value::object(vec![
+            ("target", Value::str(& self.target)),
+            ("distribution", Value::str(self.distribution.name())),
+            ("num_shards", Value::Int(self.shards.len() as u64)),
+            ("shards", Value::Array(shards))
+        ])
+    }
+}
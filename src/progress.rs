@@ -0,0 +1,154 @@
+// Progress of a running create, reported from the main thread as workers
+// report archived files.
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::json::Value;
+
+
+pub struct Progress {
+    pub files: usize,
+    pub total_files: usize,
+    // Bytes written to the shard files so far (headers and padding included)
+    pub bytes: u64,
+    // (shard index, bytes written) for every shard of the run
+    pub shard_bytes: Vec<(u32, u64)>,
+    pub elapsed: Duration,
+    // Extrapolated from the file rate so far; None until a file is done
+    pub eta: Option<Duration>
+}
+
+
+pub type ProgressFn = dyn Fn(& Progress) + Send + Sync;
+
+
+impl Progress {
+    pub fn to_json(& self) -> Value {
+        Value::object(vec![
+            ("done", Value::Int(self.files as u64)),
+            ("total", Value::Int(self.total_files as u64)),
+            ("bytes", Value::Int(self.bytes)),
+            ("shard_sizes", Value::Array(
+                self.shard_bytes.iter().map(|(idx, bytes)| Value::object(vec![
+                    ("shard", Value::Int(* idx as u64)),
+                    ("bytes", Value::Int(* bytes))
+                ])).collect()
+            )),
+            ("elapsed_secs", Value::Float(self.elapsed.as_secs_f64()))
+        ])
+    }
+}
+
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+
+// Progress line as printed by the binary (to stderr, so it does not mix with
+// plan or manifest output)
+pub fn print_progress(p: & Progress) {
+    let shards: Vec<String> = p.shard_bytes.iter()
+        .map(|(idx, bytes)| format!("{}: {:.1}", idx, megabytes(* bytes)))
+        .collect();
+    let eta = match p.eta {
+        Some(eta) => format!(", ETA {}s", eta.as_secs()),
+        None => String::new()
+    };
+    eprintln!(
+        "{} / {} files, {:.1} MB written (per shard MB: {}){}",
+        p.files, p.total_files, megabytes(p.bytes), shards.join(", "), eta
+    );
+}
+
+
+// A callback writing every report to `sink` as one line of JSON, flushed
+// right away, for a monitoring process to follow. Write errors (e.g. the
+// monitor went away) are ignored.
+pub fn json_progress(
+        sink: impl Write + Send + 'static
+    ) -> impl Fn(& Progress) + Send + Sync {
+    let sink = Mutex::new(sink);
+    move |p: & Progress| {
+        if let Ok(mut sink) = sink.lock() {
+            let _ = writeln!(sink, "{}", p.to_json())
+                .and_then(|_| sink.flush());
+        }
+    }
+}
+
+
+// Accumulates worker results and calls the progress callback at most once
+// per `interval`
+pub struct Tracker {
+    callback: Option<Arc<ProgressFn>>,
+    interval: Duration,
+    started: Instant,
+    last_report: Instant,
+    progress: Progress
+}
+
+
+impl Tracker {
+    pub fn new(
+            callback: Option<Arc<ProgressFn>>, shards: & [u32],
+            total_files: usize
+        ) -> Tracker {
+        let now = Instant::now();
+        Tracker {
+            callback,
+            interval: Duration::from_secs(1),
+            started: now,
+            last_report: now,
+            progress: Progress {
+                files: 0,
+                total_files,
+                bytes: 0,
+                shard_bytes: shards.iter().map(|idx| (* idx, 0)).collect(),
+                elapsed: Duration::ZERO,
+                eta: None
+            }
+        }
+    }
+
+    pub fn record(& mut self, shard: u32, bytes: u64) {
+        self.progress.files += 1;
+        self.progress.bytes += bytes;
+        if let Some(entry) = self.progress.shard_bytes.iter_mut()
+                .find(|(idx, _)| * idx == shard) {
+            entry.1 += bytes;
+        }
+    }
+
+    // Report the final state, whenever the last report was
+    pub fn finish(& mut self) {
+        self.report();
+    }
+
+    // Report if the interval has passed since the last report
+    pub fn tick(& mut self) {
+        if self.last_report.elapsed() >= self.interval {
+            self.report();
+        }
+    }
+
+    fn report(& mut self) {
+        let callback = match & self.callback {
+            Some(callback) => callback,
+            None => return
+        };
+        let p = & mut self.progress;
+        p.elapsed = self.started.elapsed();
+        p.eta = if p.files > 0 {
+            let remaining = p.total_files.saturating_sub(p.files) as f64;
+            Some(Duration::from_secs_f64(
+                p.elapsed.as_secs_f64() / p.files as f64 * remaining
+            ))
+        } else {
+            None
+        };
+        callback(p);
+        self.last_report = Instant::now();
+    }
+}
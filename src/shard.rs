@@ -0,0 +1,633 @@
+// Shard files written by a single worker. A shard can be split into several
+// parts when it reaches a size limit: part 0 is `{name}.{idx}.tar` (the same
+// name as an unsplit shard), and further parts are `{name}.{idx}.{part}.tar`
+// (names as given by the default NameTemplate). An archive named `-` is a
+// single stream to stdout instead. With `shard_index` every part ends in a
+// SHARD_INDEX entry that lists the members before it.
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, create_dir_all, metadata, remove_file};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tar::{Archive, Builder, EntryType, Header, HeaderMode};
+
+use crate::format::Format;
+use crate::json::Value;
+use crate::transform::EntryTransform;
+use crate::sha256::{Sha256, hash_file};
+use crate::xattrs::append_pax;
+
+
+// Archive name that streams to stdout
+pub const STDOUT: & str = "-";
+
+
+// Name of the index entry that ends a part written with `shard_index`
+pub const SHARD_INDEX: & str = ".ptar-shard-index.json";
+
+
+// PAX record that marks the index entry as one, unlike a file of the same
+// name from the archived tree. A standard key, which readers skip quietly.
+pub const SHARD_INDEX_RECORD: (& str, & str) = ("comment", "ptar-shard-index");
+
+
+// One piece of a parsed name template
+#[derive(Clone, Debug)]
+enum Segment {
+    Text(String),
+    Name,
+    // The shard index, zero-padded to this width
+    Index(usize),
+    Ext
+}
+
+
+// How shard files are named. Placeholders: {name} (the archive name),
+// {index}, {index:0N} (zero-padded to N digits) and {ext} ("tar"). Parts
+// after the first use "{index}.{part}" as their index, so the default
+// template gives `{name}.{idx}.tar` and `{name}.{idx}.{part}.tar`.
+// With directories (see with_dirs), shard N is put into the Nth of them,
+// round-robin, e.g. to stripe the shards across disks.
+#[derive(Clone, Debug)]
+pub struct NameTemplate {
+    segments: Vec<Segment>,
+    dirs: Vec<String>
+}
+
+
+impl NameTemplate {
+    pub const DEFAULT: & 'static str = "{name}.{index}.{ext}";
+
+    pub fn parse(template: & str) -> Result<NameTemplate, String> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while ! rest.is_empty() {
+            let open = rest.find('{').unwrap_or(rest.len());
+            let text = & rest[..open];
+            if text.contains('}') {
+                return Err(format!("unmatched '}}' in {}", template));
+            }
+            if ! text.is_empty() {
+                segments.push(Segment::Text(text.to_string()));
+            }
+            rest = & rest[open..];
+            if rest.is_empty() {
+                break;
+            }
+            let close = rest.find('}')
+                .ok_or(format!("unclosed '{{' in {}", template))?;
+            segments.push(match & rest[1..close] {
+                "name" => Segment::Name,
+                "index" => Segment::Index(0),
+                "ext" => Segment::Ext,
+                other => match other.strip_prefix("index:0")
+                        .and_then(|width| width.parse().ok()) {
+                    Some(width) => Segment::Index(width),
+                    None => return Err(format!(
+                        "unknown placeholder {{{}}} in {} (expected {{name}}, \
+                        {{index}}, {{index:0N}} or {{ext}})", other, template
+                    ))
+                }
+            });
+            rest = & rest[close + 1..];
+        }
+        if ! segments.iter().any(|s| matches!(s, Segment::Index(_))) {
+            return Err(format!(
+                "{} has no {{index}}, so all shards would get the same name",
+                template
+            ));
+        }
+        Ok(NameTemplate { segments, dirs: Vec::new() })
+    }
+
+    pub fn with_dirs(mut self, dirs: & [String]) -> NameTemplate {
+        self.dirs = dirs.to_vec();
+        self
+    }
+
+    pub fn path(& self, archive_name: & str, index: u32, part: u32) -> String {
+        let mut path = String::new();
+        for segment in & self.segments {
+            match segment {
+                Segment::Text(text) => path.push_str(text),
+                Segment::Name => path.push_str(archive_name),
+                Segment::Index(width) => {
+                    path.push_str(& format!("{:0width$}", index, width = width));
+                    if part > 0 {
+                        path.push_str(& format!(".{}", part));
+                    }
+                }
+                Segment::Ext => path.push_str("tar")
+            }
+        }
+        if self.dirs.is_empty() {
+            return path;
+        }
+        let dir = & self.dirs[index as usize % self.dirs.len()];
+        Path::new(dir).join(path).to_string_lossy().to_string()
+    }
+
+    // How many shards of `archive_name` exist on disk: shards 0, 1, ... up
+    // to the first whose first part is missing
+    pub fn count(& self, archive_name: & str) -> u32 {
+        let mut count = 0;
+        while Path::new(& self.path(archive_name, count, 0)).exists() {
+            count += 1;
+        }
+        count
+    }
+
+    // All parts of shard `index` that exist on disk, in order
+    pub fn parts(& self, archive_name: & str, index: u32) -> Vec<String> {
+        let mut parts = vec![self.path(archive_name, index, 0)];
+        let mut part = 1;
+        loop {
+            let path = self.path(archive_name, index, part);
+            if ! Path::new(& path).exists() {
+                return parts;
+            }
+            parts.push(path);
+            part += 1;
+        }
+    }
+}
+
+
+impl Default for NameTemplate {
+    fn default() -> NameTemplate {
+        NameTemplate::parse(NameTemplate::DEFAULT).unwrap()
+    }
+}
+
+
+// When a shard part is full. A part always receives at least one member, so
+// a single file larger than `max_bytes` still gets archived (alone).
+#[derive(Clone, Copy, Default)]
+pub struct ShardLimits {
+    pub max_members: Option<u64>,
+    pub max_bytes: Option<u64>
+}
+
+
+#[derive(Clone)]
+pub struct ShardConfig {
+    pub header_mode: HeaderMode,
+    pub limits: ShardLimits,
+    // Compute the SHA-256 of every part while it is written
+    pub checksums: bool,
+    // Add to the shard's existing parts instead of replacing them
+    pub append: bool,
+    // Leave FIFOs and device nodes out of the shard
+    pub skip_special: bool,
+    // Keep the leading "/" of absolute member names
+    pub absolute_names: bool,
+    // Precede every member with a PAX header of its extended attributes
+    pub xattrs: bool,
+    // Write files with holes as GNU sparse entries
+    pub sparse: bool,
+    pub names: NameTemplate,
+    // Refuse symlinks that point nowhere
+    pub check_symlinks: bool,
+    pub format: Format,
+    pub transform: Option<Arc<dyn EntryTransform + Send + Sync>>,
+    // Directory targets whose contents are stored without them (the targets
+    // themselves are left out)
+    pub strip_roots: Vec<PathBuf>,
+    // Continue the shards of an interrupted run (see `journal`): like
+    // `append`, but a last entry cut short by the interruption is dropped
+    pub resume: bool,
+    // Items that are further names of an earlier item (the same file), which
+    // are archived as hard links to it
+    pub hard_links: Option<Arc<HashMap<PathBuf, PathBuf>>>,
+    // Regular files larger than this are left out (see `max_file_size`)
+    pub max_file_size: Option<u64>,
+    // fsync every part once it is finished
+    pub fsync: bool,
+    // Targets and the names they are stored under (see `relative_to`)
+    pub relative_roots: Vec<(PathBuf, PathBuf)>,
+    // Targets that are symlinks to follow (see `follow_toplevel`)
+    pub followed_roots: Vec<PathBuf>,
+    // End every part with a SHARD_INDEX entry listing its members
+    pub shard_index: bool
+}
+
+
+// A shard's destination: a file, or stdout
+pub type ShardOutput = Box<dyn Write + Send>;
+
+
+// Counts (and optionally hashes) the bytes written to the underlying shard
+// file
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+    hasher: Option<Sha256>
+}
+
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W, hash: bool) -> CountingWriter<W> {
+        CountingWriter {
+            inner,
+            count: 0,
+            hasher: if hash { Some(Sha256::new()) } else { None }
+        }
+    }
+
+    // Continue counting (and hashing) a file that already holds `count`
+    // bytes, which `hasher` has seen if given
+    fn resume(
+            inner: W, count: u64, hasher: Option<Sha256>
+        ) -> CountingWriter<W> {
+        CountingWriter { inner, count, hasher }
+    }
+
+    pub fn count(& self) -> u64 {
+        self.count
+    }
+
+    pub fn digest(self) -> Option<String> {
+        self.hasher.map(|h| h.finish_hex())
+    }
+}
+
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(& mut self, buf: & [u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        if let Some(hasher) = & mut self.hasher {
+            hasher.update(& buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(& mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+// SHA-256 of one finished shard part
+pub struct PartChecksum {
+    pub path: String,
+    pub sha256: String
+}
+
+
+pub struct ShardWriter {
+    archive_name: String,
+    index: u32,
+    part: u32,
+    config: ShardConfig,
+    members: u64,
+    // Bytes in the parts that are already closed
+    closed_bytes: u64,
+    builder: Builder<CountingWriter<ShardOutput>>,
+    checksums: Vec<PartChecksum>,
+    // Written as one stream that cannot be appended to or split: stdout, or
+    // a named pipe (e.g. read by a tape system)
+    stream: bool,
+    // Names of the members of the current part (with `shard_index`)
+    listed: Vec<String>
+}
+
+
+// A shard path that is an existing FIFO is opened for writing like a file
+// (which waits for a reader), but only as a stream
+#[cfg(unix)]
+fn is_fifo(path: & str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    metadata(path).is_ok_and(|meta| meta.file_type().is_fifo())
+}
+
+
+#[cfg(not(unix))]
+fn is_fifo(_: & str) -> bool {
+    false
+}
+
+
+fn open_builder(
+        path: & str, config: & ShardConfig
+    ) -> io::Result<Builder<CountingWriter<ShardOutput>>> {
+    let output: ShardOutput = if path == STDOUT {
+        Box::new(io::stdout())
+    } else {
+        // A template can put shards in a directory
+        if let Some(parent) = Path::new(path).parent() {
+            create_dir_all(parent)?;
+        }
+        Box::new(File::create(path)?)
+    };
+    let mut builder = Builder::new(
+        CountingWriter::new(output, config.checksums)
+    );
+    builder.mode(config.header_mode);
+    Ok(builder)
+}
+
+
+// Write the end-of-archive marker and record the part's checksum (if it was
+// computed). With `sync` the part is also flushed to disk (see sync_part).
+fn close_part(
+        builder: Builder<CountingWriter<ShardOutput>>, path: String,
+        checksums: & mut Vec<PartChecksum>, sync: bool
+    ) -> io::Result<()> {
+    let mut writer = builder.into_inner()?;
+    writer.flush()?;
+    if sync {
+        sync_part(& path)?;
+    }
+    if let Some(sha256) = writer.digest() {
+        checksums.push(PartChecksum { path, sha256 });
+    }
+    Ok(())
+}
+
+
+// Make a finished part durable: its data, and on Unix the directory entry
+// of a newly created file. fsync applies to the file, not to the descriptor,
+// so a new descriptor is as good as the one it was written through.
+fn sync_part(path: & str) -> io::Result<()> {
+    File::open(path)?.sync_all()?;
+    #[cfg(unix)]
+    {
+        let dir = match Path::new(path).parent() {
+            Some(dir) if ! dir.as_os_str().is_empty() => dir,
+            _ => Path::new(".")
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+
+// Offset just past the last entry of a Tar file, where its end-of-archive
+// marker starts, and the number of entries. With `truncated`, the file may
+// end in the middle of an entry (the writer was killed), which is then
+// treated as the end.
+fn archive_end(path: & str, truncated: bool) -> io::Result<(u64, u64)> {
+    let len = metadata(path)?.len();
+    let mut ar = Archive::new(File::open(path)?);
+    let mut end = 0;
+    let mut members = 0;
+    for entry in ar.entries_with_seek()? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) if truncated => break,
+            Err(error) => return Err(error)
+        };
+        // The stored size: not the same as size() for sparse entries
+        let stored = entry.header().entry_size()?;
+        let entry_end = entry.raw_file_position() + stored.div_ceil(512) * 512;
+        if truncated && entry_end > len {
+            break;
+        }
+        end = entry_end;
+        members += 1;
+    }
+    Ok((end, members))
+}
+
+
+impl ShardWriter {
+    // Start the shard afresh, or with `config.append` continue its last
+    // existing part
+    pub fn new(
+            archive_name: & str, index: u32, config: ShardConfig
+        ) -> io::Result<ShardWriter> {
+
+        let path = if archive_name == STDOUT {
+            STDOUT.to_string()
+        } else {
+            config.names.path(archive_name, index, 0)
+        };
+        let stream = path == STDOUT || is_fifo(& path);
+        if (config.append || config.resume) && ! stream
+                && Path::new(& path).exists() {
+            return ShardWriter::append(archive_name, index, config);
+        }
+        let builder = open_builder(& path, & config)?;
+        Ok(ShardWriter {
+            archive_name: archive_name.to_string(),
+            index,
+            part: 0,
+            config,
+            members: 0,
+            closed_bytes: 0,
+            builder,
+            checksums: Vec::new(),
+            stream,
+            listed: Vec::new()
+        })
+    }
+
+    // Reopen the last part of an existing shard, cutting off its
+    // end-of-archive marker (Builder writes a new one on finish). Earlier
+    // parts are complete and are only counted, and hashed if checksums are
+    // requested (the sums file covers every part).
+    fn append(
+            archive_name: & str, index: u32, config: ShardConfig
+        ) -> io::Result<ShardWriter> {
+
+        let parts = config.names.parts(archive_name, index);
+        let (last, earlier) = parts.split_last().unwrap();
+        let mut checksums = Vec::new();
+        let mut closed_bytes = 0;
+        for path in earlier {
+            closed_bytes += metadata(path)?.len();
+            if config.checksums {
+                checksums.push(PartChecksum {
+                    path: path.clone(), sha256: hash_file(Path::new(path))?
+                });
+            }
+        }
+
+        let (end, members) = archive_end(last, config.resume)?;
+        let mut file = OpenOptions::new().read(true).write(true).open(last)?;
+        file.set_len(end)?;
+        let hasher = if config.checksums {
+            let mut hasher = Sha256::new();
+            io::copy(& mut (& mut file).take(end), & mut hasher)?;
+            Some(hasher)
+        } else {
+            None
+        };
+        file.seek(SeekFrom::End(0))?;
+
+        let output: ShardOutput = Box::new(file);
+        let mut builder = Builder::new(
+            CountingWriter::resume(output, end, hasher)
+        );
+        builder.mode(config.header_mode);
+        Ok(ShardWriter {
+            archive_name: archive_name.to_string(),
+            index,
+            part: earlier.len() as u32,
+            config,
+            members,
+            closed_bytes,
+            builder,
+            checksums,
+            stream: false,
+            listed: Vec::new()
+        })
+    }
+
+    pub fn path(& self) -> String {
+        if self.archive_name == STDOUT {
+            return STDOUT.to_string();
+        }
+        self.config.names.path(& self.archive_name, self.index, self.part)
+    }
+
+    // Bytes written to all parts of this shard so far
+    pub fn written(& self) -> u64 {
+        self.closed_bytes + self.builder.get_ref().count()
+    }
+
+    // Whether finished parts are fsynced (a stream cannot be)
+    fn syncs(& self) -> bool {
+        self.config.fsync && ! self.stream
+    }
+
+    // Note a member of the current part for its index entry (a no-op
+    // without `shard_index`)
+    pub fn list_member(& mut self, name: & Path) {
+        if self.config.shard_index {
+            self.listed.push(name.to_string_lossy().to_string());
+        }
+    }
+
+    // Append the index entry of the current part: JSON with the shard, the
+    // part and the names of its members, in order. It has a fixed mode and
+    // time, so that deterministic shards stay reproducible.
+    fn append_index(& mut self) -> io::Result<()> {
+        if ! self.config.shard_index {
+            return Ok(());
+        }
+        let members = mem::take(& mut self.listed);
+        let index = Value::object(vec![
+            ("shard", Value::Int(self.index as u64)),
+            ("part", Value::Int(self.part as u64)),
+            ("members", Value::Array(
+                members.iter().map(|name| Value::str(name)).collect()
+            ))
+        ]).pretty() + "\n";
+        let mut header = match self.config.format {
+            Format::Gnu => Header::new_gnu(),
+            Format::Pax | Format::Ustar => Header::new_ustar()
+        };
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(index.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        let (key, value) = SHARD_INDEX_RECORD;
+        append_pax(
+            & mut self.builder,
+            & [(key.as_bytes().to_vec(), value.as_bytes().to_vec())]
+        )?;
+        self.builder.append_data(& mut header, SHARD_INDEX, index.as_bytes())
+    }
+
+    // A stream is never split
+    fn is_full(& self) -> bool {
+        if self.members == 0 || self.stream {
+            return false;
+        }
+        let written = self.builder.get_ref().count();
+        self.config.limits.max_members.is_some_and(|max| self.members >= max)
+            || self.config.limits.max_bytes.is_some_and(|max| written >= max)
+    }
+
+
+    // Returns the builder the next member should be appended to, first
+    // finishing the current part and starting a new one if it is full
+    pub fn next_member(
+            & mut self
+        ) -> io::Result<& mut Builder<CountingWriter<ShardOutput>>> {
+        if self.is_full() {
+            self.append_index()?;
+            let next = open_builder(
+                & self.config.names.path(
+                    & self.archive_name, self.index, self.part + 1
+                ),
+                & self.config
+            )?;
+            let full = mem::replace(& mut self.builder, next);
+            self.closed_bytes += full.get_ref().count();
+            let sync = self.syncs();
+            close_part(full, self.path(), & mut self.checksums, sync)?;
+            self.part += 1;
+            self.members = 0;
+        }
+        self.members += 1;
+        Ok(& mut self.builder)
+    }
+
+    // Finish the last part, returning the checksums of all parts (empty
+    // unless checksums were requested)
+    pub fn finish(mut self) -> io::Result<Vec<PartChecksum>> {
+        self.append_index()?;
+        let path = self.path();
+        let sync = self.syncs();
+        close_part(self.builder, path, & mut self.checksums, sync)?;
+        if self.archive_name == STDOUT {
+            return Ok(self.checksums);
+        }
+        // Parts left over from an earlier, larger run would otherwise be
+        // picked up by extract
+        let mut stale = self.part + 1;
+        while remove_file(
+            self.config.names.path(& self.archive_name, self.index, stale)
+        ).is_ok() {
+            stale += 1;
+        }
+        Ok(self.checksums)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(template: & str, index: u32, part: u32) -> String {
+        NameTemplate::parse(template).unwrap().path("backup", index, part)
+    }
+
+    #[test]
+    fn default_template() {
+        assert_eq!(path(NameTemplate::DEFAULT, 3, 0), "backup.3.tar");
+        assert_eq!(path(NameTemplate::DEFAULT, 3, 2), "backup.3.2.tar");
+    }
+
+    #[test]
+    fn padded_index() {
+        assert_eq!(path("{name}-{index:03}.{ext}", 7, 0), "backup-007.tar");
+        assert_eq!(path("{name}-{index:03}.{ext}", 1234, 0), "backup-1234.tar");
+        assert_eq!(path("{name}-{index:03}.{ext}", 7, 1), "backup-007.1.tar");
+        assert_eq!(path("shards/{index}", 0, 0), "shards/0");
+    }
+
+    #[test]
+    fn dirs_are_round_robin() {
+        let names = NameTemplate::default()
+            .with_dirs(& ["/a".to_string(), "/b".to_string()]);
+        assert_eq!(names.path("x", 0, 0), "/a/x.0.tar");
+        assert_eq!(names.path("x", 1, 0), "/b/x.1.tar");
+        assert_eq!(names.path("x", 2, 1), "/a/x.2.1.tar");
+    }
+
+    #[test]
+    fn parse_rejects_bad_templates() {
+        for template in [
+            "{name}.{idx}.tar", "{name}.{index:3}", "{index", "{index}}",
+            "{name}.tar", ""
+        ] {
+            assert!(NameTemplate::parse(template).is_err(), "{}", template);
+        }
+    }
+}
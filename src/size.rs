@@ -0,0 +1,101 @@
+// Human-readable byte sizes ("512M", "1.5G", "4096"). Units are binary
+// (K = 1024) and case-insensitive; a trailing "B" or "iB" is accepted.
+use std::time::Duration;
+
+
+pub fn parse_size(text: & str) -> Result<u64, String> {
+    let trimmed = text.trim();
+    let lower = trimmed.to_ascii_lowercase();
+    let digits_end = lower
+        .find(|c: char| ! (c.is_ascii_digit() || c == '.'))
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(digits_end);
+
+    let shift = match unit.trim_end_matches("ib").trim_end_matches('b') {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        "p" => 50,
+        _ => return Err(format!("invalid size '{}': unknown unit", text))
+    };
+    let value: f64 = number.parse()
+        .map_err(|_| format!("invalid size '{}': expected e.g. 512M or 1G", text))?;
+
+    let bytes = value * (1u64 << shift) as f64;
+    if ! bytes.is_finite() || bytes >= u64::MAX as f64 {
+        return Err(format!("invalid size '{}': too large", text));
+    }
+    Ok(bytes as u64)
+}
+
+
+// Durations on the command line: "90" or "90s", "30m", "2h", "1d", with a
+// fraction allowed ("1.5h")
+pub fn parse_duration(text: & str) -> Result<Duration, String> {
+    let trimmed = text.trim().to_ascii_lowercase();
+    let (number, secs_per_unit) = match trimmed.strip_suffix(
+        |c: char| c.is_ascii_alphabetic()
+    ) {
+        Some(number) => (number, match & trimmed[number.len()..] {
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            _ => return Err(format!(
+                "invalid duration '{}': unknown unit", text
+            ))
+        }),
+        None => (trimmed.as_str(), 1.0)
+    };
+    let value: f64 = number.parse().map_err(|_| format!(
+        "invalid duration '{}': expected e.g. 90s, 30m or 2h", text
+    ))?;
+    Duration::try_from_secs_f64(value * secs_per_unit)
+        .map_err(|_| format!("invalid duration '{}': out of range", text))
+}
+
+
+// The inverse for reports: "4.2 GB", "512 B" (binary units, one decimal)
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+
+// "41 files (2.4 MB) in 1.2s: 34 files/s, 2.0 MB/s"
+pub fn format_throughput(files: u64, bytes: u64, elapsed: Duration) -> String {
+    // A run too short to measure still divides by something
+    let secs = elapsed.as_secs_f64().max(0.001);
+    format!(
+        "{} files ({}) in {:.1}s: {} files/s, {}/s",
+        format_count(files), format_size(bytes), elapsed.as_secs_f64(),
+        format_count((files as f64 / secs) as u64),
+        format_size((bytes as f64 / secs) as u64)
+    )
+}
+
+
+// "12431" -> "12,431"
+pub fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut out = String::new();
+    for (idx, c) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
@@ -0,0 +1,131 @@
+// Sparse files (create --sparse): only the data regions of a file with holes
+// are stored, in a GNU sparse entry (type 'S'), and Entry::unpack recreates
+// the holes by seeking. Regions are found with SEEK_DATA/SEEK_HOLE, which
+// only Linux is asked for; elsewhere every file is archived in full.
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use tar::{EntryType, GnuExtSparseHeader, GnuSparseHeader, Header};
+
+
+// Largest value of the 12-byte octal fields of a sparse map (Archive reads
+// no other encoding there), so larger files are archived in full
+const MAX_OFFSET: u64 = 0o77777777777;
+
+
+// Regions in the GNU header itself, and in every extension header after it
+const HEADER_REGIONS: usize = 4;
+const EXT_REGIONS: usize = 21;
+
+
+// (offset, length) of the data regions of `file`, or None if it has no holes
+// worth skipping. Regions are widened to 512-byte boundaries, which Archive
+// requires of all but the last, and a file ending in a hole gets an empty
+// region at its end.
+#[cfg(target_os = "linux")]
+pub fn data_regions(
+        file: & File, meta: & Metadata
+    ) -> io::Result<Option<Vec<(u64, u64)>>> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+
+    let size = meta.len();
+    // Fully allocated files are not worth asking about
+    if meta.blocks() * 512 >= size || size > MAX_OFFSET {
+        return Ok(None);
+    }
+    let fd = file.as_raw_fd();
+    let mut regions: Vec<(u64, u64)> = Vec::new();
+    let mut pos = 0;
+    while pos < size {
+        let start = unsafe { libc::lseek(fd, pos as i64, libc::SEEK_DATA) };
+        if start < 0 {
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                // Only a hole is left
+                Some(libc::ENXIO) => break,
+                // The file system cannot tell
+                Some(libc::EINVAL) => return Ok(None),
+                _ => return Err(error)
+            }
+        }
+        let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+        if end < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let start = start as u64 / 512 * 512;
+        let end = (end as u64).div_ceil(512).saturating_mul(512).min(size);
+        match regions.last_mut() {
+            Some((offset, len)) if * offset + * len >= start => {
+                * len = end - * offset;
+            }
+            _ => regions.push((start, end - start))
+        }
+        pos = end;
+    }
+    if regions.last().is_none_or(|(offset, len)| offset + len < size) {
+        regions.push((size, 0));
+    }
+    Ok(Some(regions))
+}
+
+
+#[cfg(not(target_os = "linux"))]
+pub fn data_regions(
+        _: & File, _: & Metadata
+    ) -> io::Result<Option<Vec<(u64, u64)>>> {
+    Ok(None)
+}
+
+
+fn octal(field: & mut [u8; 12], value: u64) {
+    field.copy_from_slice(format!("{:011o}\0", value).as_bytes());
+}
+
+
+fn set_region(slot: & mut GnuSparseHeader, region: & (u64, u64)) {
+    octal(& mut slot.offset, region.0);
+    octal(& mut slot.numbytes, region.1);
+}
+
+
+// Write `header` (already named and filled from the file's metadata) as a
+// sparse entry holding the `regions` of `file`, followed by the extension
+// headers for the regions that do not fit and the regions' data
+pub fn write_sparse<W: Write>(
+        out: & mut W, header: & mut Header, file: & mut File,
+        regions: & [(u64, u64)]
+    ) -> io::Result<()> {
+    let real_size = regions.last().map_or(0, |(offset, len)| offset + len);
+    let stored: u64 = regions.iter().map(|(_, len)| len).sum();
+    header.set_entry_type(EntryType::GNUSparse);
+    header.set_size(stored);
+
+    let (first, rest) = regions.split_at(regions.len().min(HEADER_REGIONS));
+    let gnu = header.as_gnu_mut().unwrap();
+    octal(& mut gnu.realsize, real_size);
+    for (slot, region) in gnu.sparse.iter_mut().zip(first) {
+        set_region(slot, region);
+    }
+    gnu.isextended[0] = ! rest.is_empty() as u8;
+    header.set_cksum();
+    out.write_all(header.as_bytes())?;
+
+    let chunks: Vec<& [(u64, u64)]> = rest.chunks(EXT_REGIONS).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut ext = GnuExtSparseHeader::new();
+        for (slot, region) in ext.sparse.iter_mut().zip(chunk.iter()) {
+            set_region(slot, region);
+        }
+        ext.isextended[0] = (i + 1 < chunks.len()) as u8;
+        out.write_all(ext.as_bytes())?;
+    }
+
+    for (offset, len) in regions {
+        file.seek(SeekFrom::Start(* offset))?;
+        if io::copy(& mut (& mut * file).take(* len), out)? != * len {
+            return Err(io::Error::other("file shrank while being archived"));
+        }
+    }
+    let padding = (512 - stored % 512) % 512;
+    out.write_all(& vec![0; padding as usize])
+}
@@ -0,0 +1,104 @@
+// Rewriting member names at archive time (e.g. redacting or moving a prefix),
+// for pipelines that need the archived paths to differ from the ones on disk.
+// The files are still read from their original paths.
+use std::path::{Path, PathBuf};
+
+
+pub trait EntryTransform {
+    // The name to archive `original` under (leading "/" is handled as
+    // usual afterwards), or None to leave it out of the archive
+    fn map_path(& self, original: & Path) -> Option<PathBuf>;
+}
+
+
+// Replace a leading `old` (whole path components) with `new`, like
+// `tar --transform 's,^old,new,'` restricted to prefixes. Paths outside
+// `old` are archived unchanged; `old` itself is left out if `new` is empty.
+#[derive(Clone, Debug)]
+pub struct PrefixRewrite {
+    pub old: PathBuf,
+    pub new: PathBuf
+}
+
+
+impl PrefixRewrite {
+    pub fn new(old: & str, new: & str) -> PrefixRewrite {
+        PrefixRewrite { old: PathBuf::from(old), new: PathBuf::from(new) }
+    }
+
+    // "s/old/new/" (any delimiter after the "s"; the trailing one is
+    // optional)
+    pub fn parse(expr: & str) -> Result<PrefixRewrite, String> {
+        let invalid = || format!(
+            "invalid transform '{}': expected s/OLD/NEW/", expr
+        );
+        let rest = expr.strip_prefix('s').ok_or_else(invalid)?;
+        let delimiter = rest.chars().next().ok_or_else(invalid)?;
+        let parts: Vec<& str> = rest[delimiter.len_utf8()..]
+            .split(delimiter).collect();
+        match parts.as_slice() {
+            [old, new] | [old, new, ""] if ! old.is_empty() => {
+                Ok(PrefixRewrite::new(old, new))
+            }
+            _ => Err(invalid())
+        }
+    }
+}
+
+
+impl EntryTransform for PrefixRewrite {
+    fn map_path(& self, original: & Path) -> Option<PathBuf> {
+        match original.strip_prefix(& self.old) {
+            // join would add a trailing "/"
+            Ok(rest) if rest.as_os_str().is_empty() => {
+                (! self.new.as_os_str().is_empty()).then(|| self.new.clone())
+            }
+            Ok(rest) => Some(self.new.join(rest)),
+            Err(_) => Some(original.to_path_buf())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(expr: & str) -> Option<(PathBuf, PathBuf)> {
+        PrefixRewrite::parse(expr).ok()
+            .map(|rewrite| (rewrite.old, rewrite.new))
+    }
+
+    #[test]
+    fn parse_accepts_any_delimiter() {
+        let expected = Some((PathBuf::from("a/b"), PathBuf::from("c")));
+        assert_eq!(parsed("s|a/b|c|"), expected);
+        assert_eq!(parsed("s#a/b#c"), expected);
+        assert_eq!(parsed("s/old/new/"), Some((
+            PathBuf::from("old"), PathBuf::from("new")
+        )));
+    }
+
+    #[test]
+    fn parse_allows_an_empty_replacement() {
+        assert_eq!(
+            parsed("s/old//"), Some((PathBuf::from("old"), PathBuf::new()))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_expressions() {
+        for expr in ["", "s", "x/a/b/", "s/a", "s//b/", "s/a/b/c", "s/a/b/c/"] {
+            assert_eq!(parsed(expr), None, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn map_path_rewrites_whole_components() {
+        let rewrite = PrefixRewrite::new("a/b", "c");
+        assert_eq!(rewrite.map_path(Path::new("a/b/f")), Some("c/f".into()));
+        assert_eq!(rewrite.map_path(Path::new("a/b")), Some("c".into()));
+        assert_eq!(rewrite.map_path(Path::new("a/bc")), Some("a/bc".into()));
+        assert_eq!(PrefixRewrite::new("a", "").map_path(Path::new("a")), None);
+    }
+}
@@ -0,0 +1,130 @@
+// Extended attributes (user xattrs, SELinux labels, ...) of archived files,
+// stored as `SCHILY.xattr.<name>` records of a PAX extended header, the
+// format GNU tar and Entry::unpack use. Only Unix has them; elsewhere a file
+// has none.
+use std::io::{self, Read, Write};
+use std::path::Path;
+use tar::{Builder, Entry, EntryType, Header};
+
+
+// PAX records (key, value) for the extended attributes of `path` itself
+// (symlinks are not followed)
+#[cfg(unix)]
+pub fn records(path: & Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    use std::os::unix::ffi::OsStrExt;
+    let mut records = Vec::new();
+    for name in xattr::list(path)? {
+        if let Some(value) = xattr::get(path, & name)? {
+            let key = [b"SCHILY.xattr.".as_slice(), name.as_bytes()].concat();
+            records.push((key, value));
+        }
+    }
+    Ok(records)
+}
+
+
+#[cfg(not(unix))]
+pub fn records(_: & Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+
+// Set the extended attributes among PAX `records` on `path`.
+// Entry::unpack does this for files, but not for directories.
+#[cfg(unix)]
+pub fn restore(path: & Path, records: & [(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    for (key, value) in records {
+        if let Some(name) = key.strip_prefix(b"SCHILY.xattr.".as_slice()) {
+            xattr::set(path, OsStr::from_bytes(name), value)?;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(not(unix))]
+pub fn restore(_: & Path, _: & [(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+    Ok(())
+}
+
+
+// PAX records of an entry read from an archive
+pub fn entry_records<R: Read>(
+        entry: & mut Entry<R>
+    ) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Ok(match entry.pax_extensions()? {
+        Some(extensions) => extensions.filter_map(|e| e.ok())
+            .map(|e| (e.key_bytes().to_vec(), e.value_bytes().to_vec()))
+            .collect(),
+        None => Vec::new()
+    })
+}
+
+
+// One PAX record: "<length> <key>=<value>\n", where the length counts the
+// whole record, itself included
+fn pax_record(key: & [u8], value: & [u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let mut record = format!("{} ", len).into_bytes();
+    record.extend_from_slice(key);
+    record.push(b'=');
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+
+// Write a PAX extended header holding `records` (key, value), which applies
+// to the entry appended next. Nothing is written for no records.
+pub fn append_pax<W: Write>(
+        builder: & mut Builder<W>, records: & [(Vec<u8>, Vec<u8>)]
+    ) -> io::Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let data: Vec<u8> = records.iter()
+        .flat_map(|(key, value)| pax_record(key, value))
+        .collect();
+    let mut header = Header::new_ustar();
+    header.set_path("././@PaxHeader")?;
+    header.set_entry_type(EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(& header, data.as_slice())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The length a record starts with
+    fn stated_len(record: & [u8]) -> usize {
+        let digits = record.iter().position(|& b| b == b' ').unwrap();
+        std::str::from_utf8(& record[..digits]).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn pax_record_counts_itself() {
+        // Across the lengths where the count gains a digit (10, 100, 1000)
+        for len in 0..1100 {
+            let record = pax_record(b"key", & vec![b'v'; len]);
+            assert_eq!(stated_len(& record), record.len(), "value {}", len);
+        }
+    }
+
+    #[test]
+    fn pax_record_layout() {
+        assert_eq!(pax_record(b"path", b"a"), b"9 path=a\n");
+        // 9 bytes without the count: one digit makes 10, which takes two
+        assert_eq!(pax_record(b"path", b"ab"), b"11 path=ab\n");
+    }
+}
@@ -0,0 +1,122 @@
+// Helpers shared by the integration tests: scratch trees, reading shards
+// back, and files that cannot be opened even when the tests run as root
+#![allow(dead_code)]
+use std::fs::{File, create_dir_all, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use tar::Archive;
+
+
+static NEXT: AtomicU32 = AtomicU32::new(0);
+
+
+// A directory below the temporary directory, removed when dropped
+pub struct Scratch(PathBuf);
+
+
+impl Scratch {
+    pub fn new(name: & str) -> Scratch {
+        let dir = std::env::temp_dir().join(format!(
+            "parallel-tar-test.{}.{}.{}",
+            process::id(), NEXT.fetch_add(1, Ordering::SeqCst), name
+        ));
+        create_dir_all(& dir).unwrap();
+        Scratch(dir)
+    }
+
+    pub fn join(& self, path: & str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    // `join` as the string ArchiveOptions and Archiver take
+    pub fn str(& self, path: & str) -> String {
+        self.join(path).to_string_lossy().to_string()
+    }
+
+    // Write `contents` to `path` below the scratch directory, creating the
+    // directories it is in
+    pub fn file(& self, path: & str, contents: & str) -> PathBuf {
+        let path = self.join(path);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(& path, contents).unwrap();
+        path
+    }
+}
+
+
+impl Drop for Scratch {
+    fn drop(& mut self) {
+        let _ = remove_dir_all(& self.0);
+    }
+}
+
+
+// The member names of a Tar file, in order
+pub fn members(tar_path: & Path) -> Vec<String> {
+    let mut ar = Archive::new(File::open(tar_path).unwrap());
+    ar.entries().unwrap()
+        .map(|entry| entry.unwrap().path().unwrap()
+            .to_string_lossy().to_string())
+        .collect()
+}
+
+
+// Take away every permission on `path`
+#[cfg(unix)]
+pub fn make_unreadable(path: & Path) {
+    use std::fs::{Permissions, set_permissions};
+    use std::os::unix::fs::PermissionsExt;
+    set_permissions(path, Permissions::from_mode(0o000)).unwrap();
+}
+
+
+// Run `f` without the capabilities that let root open any file
+// (CAP_DAC_OVERRIDE and CAP_DAC_READ_SEARCH), so that a file without
+// permissions cannot be opened. Capabilities belong to a thread: only the
+// calling thread and the threads it starts (e.g. create's workers) lose
+// them, and they are given back afterwards.
+#[cfg(target_os = "linux")]
+pub fn without_dac_override<T>(f: impl FnOnce() -> T) -> T {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: i32
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32
+    }
+
+    const VERSION_3: u32 = 0x2008_0522;
+    const DAC: u32 = (1 << 1) | (1 << 2);
+    let mut header = CapHeader { version: VERSION_3, pid: 0 };
+    let mut saved = [CapData::default(); 2];
+    let capset = |header: & mut CapHeader, data: & [CapData; 2]| {
+        let result = unsafe {
+            libc::syscall(libc::SYS_capset, header, data.as_ptr())
+        };
+        assert_eq!(result, 0, "capset failed");
+    };
+    let result = unsafe {
+        libc::syscall(libc::SYS_capget, & mut header, saved.as_mut_ptr())
+    };
+    assert_eq!(result, 0, "capget failed");
+    let mut dropped = saved;
+    dropped[0].effective &= ! DAC;
+    capset(& mut header, & dropped);
+    let result = f();
+    capset(& mut header, & saved);
+    result
+}
+
+
+#[cfg(not(target_os = "linux"))]
+pub fn without_dac_override<T>(f: impl FnOnce() -> T) -> T {
+    f()
+}
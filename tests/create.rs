@@ -0,0 +1,268 @@
+// Creating shards through the library, and reading them back with the tar
+// crate
+mod common;
+
+use std::fs::{File, create_dir_all, write};
+
+use parallel_tar::{ArchiveOptions, Archiver, ArchiverError, xattrs};
+use parallel_tar::format::Format;
+use tar::{Archive, EntryType};
+
+use common::{Scratch, members};
+
+
+// The members of shard `idx` of `archive`, relative to `base` (which the
+// member names start with, less its leading '/')
+fn relative_members(
+        scratch: & Scratch, archive: & str, idx: u32, base: & str
+    ) -> Vec<String> {
+    let prefix = scratch.str(base).trim_start_matches('/').to_string();
+    members(& scratch.join(& format!("{}.{}.tar", archive, idx))).iter()
+        .map(|name| name.trim_start_matches('/'))
+        .map(|name| name.strip_prefix(& prefix).unwrap_or(name)
+            .trim_start_matches('/').to_string())
+        .collect()
+}
+
+
+// An unreadable file whose name needs a PAX `path` record must not leave
+// that record behind for the next member
+#[cfg(unix)]
+#[test]
+fn unreadable_file_leaves_no_pax_header() {
+    let scratch = Scratch::new("pax-unreadable");
+    let long = "b".repeat(120);
+    scratch.file("t/a", "a");
+    common::make_unreadable(& scratch.file(& format!("t/{}", long), "b"));
+    scratch.file("t/c", "c");
+
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .num_threads(1)
+        .deterministic(true)
+        .format(Format::Pax)
+        .ignore_errors(true);
+    let report = common::without_dac_override(
+        || Archiver::new(& scratch.str("out/x"), options).create()
+    ).unwrap();
+
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(
+        relative_members(& scratch, "out/x", 0, "t"), ["", "a", "c"]
+    );
+}
+
+
+// Likewise for the GNU long name entry of an absolute name (-P)
+#[cfg(unix)]
+#[test]
+fn unreadable_file_leaves_no_long_name() {
+    let scratch = Scratch::new("absolute-unreadable");
+    let long = "b".repeat(120);
+    scratch.file("t/a", "a");
+    common::make_unreadable(& scratch.file(& format!("t/{}", long), "b"));
+    scratch.file("t/c", "c");
+
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .num_threads(1)
+        .deterministic(true)
+        .format(Format::Gnu)
+        .absolute_names(true)
+        .ignore_errors(true);
+    let report = common::without_dac_override(
+        || Archiver::new(& scratch.str("out/x"), options).create()
+    ).unwrap();
+
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(
+        relative_members(& scratch, "out/x", 0, "t"), ["", "a", "c"]
+    );
+}
+
+
+// Likewise for the PAX header of extended attributes with --xattrs. A
+// security.* attribute can be read without permission on the file, so only
+// the open fails.
+#[cfg(target_os = "linux")]
+#[test]
+fn unreadable_file_leaves_no_xattrs() {
+    let scratch = Scratch::new("xattrs-unreadable");
+    scratch.file("t/a", "a");
+    let unreadable = scratch.file("t/b", "b");
+    scratch.file("t/c", "c");
+    if xattr::set(& unreadable, "security.ptar", b"b").is_err() {
+        eprintln!("security.* extended attributes are not supported");
+        return;
+    }
+    common::make_unreadable(& unreadable);
+
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .num_threads(1)
+        .deterministic(true)
+        .format(Format::Gnu)
+        .xattrs(true)
+        .ignore_errors(true);
+    let report = common::without_dac_override(
+        || Archiver::new(& scratch.str("out/x"), options).create()
+    ).unwrap();
+
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(
+        relative_members(& scratch, "out/x", 0, "t"), ["", "a", "c"]
+    );
+    let mut ar = Archive::new(File::open(scratch.join("out/x.0.tar")).unwrap());
+    for entry in ar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let records = xattrs::entry_records(& mut entry).unwrap();
+        assert!(records.is_empty(), "{:?} has xattrs", entry.path().unwrap());
+    }
+}
+
+
+#[test]
+fn create_refuses_zero_threads() {
+    let scratch = Scratch::new("zero-threads");
+    scratch.file("t/a", "a");
+    let options = ArchiveOptions::new(& scratch.str("t")).num_threads(0);
+    let result = Archiver::new(& scratch.str("out/x"), options).create();
+    assert!(matches!(result, Err(ArchiverError::InvalidArg(_))));
+}
+
+
+// The index of a shard that is appended to would describe only the new
+// members, with the old index left in the middle
+#[test]
+fn create_refuses_shard_index_with_append_or_journal() {
+    let scratch = Scratch::new("shard-index-append");
+    scratch.file("t/a", "a");
+    let journal = scratch.str("journal");
+    let refused = [
+        ArchiveOptions::new(& scratch.str("t")).append(true),
+        ArchiveOptions::new(& scratch.str("t")).journal(Some(& journal))
+    ];
+    for options in refused {
+        let options = options.num_threads(1).shard_index(true);
+        let result = Archiver::new(& scratch.str("out/x"), options).create();
+        assert!(matches!(result, Err(ArchiverError::InvalidArg(_))));
+    }
+    assert!(! scratch.join("out").exists());
+    assert!(! scratch.join("journal").exists());
+}
+
+
+// Within every shard, deterministic members come in sorted order, the same
+// on every run however the workers are scheduled
+#[test]
+fn deterministic_member_order() {
+    let scratch = Scratch::new("deterministic-order");
+    for idx in 0..60 {
+        scratch.file(& format!("t/d{}/f{}", idx % 7, idx), & "x".repeat(idx));
+    }
+    for run in ["one", "two"] {
+        let options = ArchiveOptions::new(& scratch.str("t"))
+            .num_threads(4)
+            .deterministic(true);
+        Archiver::new(& scratch.str(run), options).create().unwrap();
+    }
+
+    for idx in 0..4 {
+        let one = members(& scratch.join(& format!("one.{}.tar", idx)));
+        let two = members(& scratch.join(& format!("two.{}.tar", idx)));
+        assert_eq!(one, two);
+        let mut sorted = one.clone();
+        sorted.sort();
+        assert_eq!(one, sorted);
+    }
+}
+
+
+// Shards written into the target are not archived, neither while they are
+// written nor by a later run that replaces them
+#[test]
+fn shards_inside_the_target_are_left_out() {
+    let scratch = Scratch::new("destination-inside");
+    scratch.file("t/a", "a");
+    scratch.file("t/d/b", "b");
+    for _ in 0..2 {
+        let options = ArchiveOptions::new(& scratch.str("t")).num_threads(2);
+        Archiver::new(& scratch.str("t/out/x"), options).create().unwrap();
+    }
+
+    let mut archived = Vec::new();
+    for idx in 0..2 {
+        archived.extend(relative_members(& scratch, "t/out/x", idx, "t"));
+    }
+    archived.sort();
+    assert_eq!(archived, ["", "a", "d", "d/b", "out"]);
+}
+
+
+// A target that is a regular file is archived as the only member
+#[test]
+fn file_target_is_the_only_member() {
+    let scratch = Scratch::new("file-target");
+    scratch.file("t/a", "a");
+    scratch.file("t/b", "b");
+    let options = ArchiveOptions::new(& scratch.str("t/a")).num_threads(2);
+    Archiver::new(& scratch.str("out/x"), options).create().unwrap();
+
+    let mut archived = Vec::new();
+    for idx in 0..2 {
+        archived.extend(relative_members(& scratch, "out/x", idx, "t"));
+    }
+    assert_eq!(archived, ["a"]);
+}
+
+
+// Every call of more_targets adds to the targets, like excludes
+#[test]
+fn more_targets_add_up() {
+    let scratch = Scratch::new("more-targets");
+    scratch.file("t/a", "a");
+    scratch.file("u/b", "b");
+    scratch.file("v/c", "c");
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .more_targets(& [scratch.str("u")])
+        .more_targets(& [scratch.str("v")])
+        .num_threads(1)
+        .deterministic(true);
+    Archiver::new(& scratch.str("out/x"), options).create().unwrap();
+
+    let archived = members(& scratch.join("out/x.0.tar"));
+    for file in ["t/a", "u/b", "v/c"] {
+        let name = scratch.str(file).trim_start_matches('/').to_string();
+        assert!(archived.contains(& name), "{} is not in {:?}", name, archived);
+    }
+}
+
+
+// Absolute names (-P) fill the 100 byte name field; only longer ones get a
+// GNU long name entry
+#[test]
+fn absolute_names_at_the_name_field_boundary() {
+    let scratch = Scratch::new("name-boundary");
+    let dir = scratch.str("t");
+    create_dir_all(& dir).unwrap();
+    let mut names = Vec::new();
+    for len in [99, 100, 101] {
+        let name = format!("{}/{}", dir, "n".repeat(len - dir.len() - 1));
+        assert_eq!(name.len(), len);
+        write(& name, "n").unwrap();
+        names.push(name);
+    }
+    let options = ArchiveOptions::new(& dir)
+        .num_threads(1)
+        .deterministic(true)
+        .absolute_names(true);
+    Archiver::new(& scratch.str("out/x"), options).create().unwrap();
+
+    let tar_path = scratch.join("out/x.0.tar");
+    let archived = members(& tar_path);
+    assert_eq!(archived[0], dir);
+    assert_eq!(archived[1..], names);
+    let mut ar = Archive::new(File::open(& tar_path).unwrap());
+    let long_names = ar.entries().unwrap().raw(true)
+        .filter(|entry| entry.as_ref().unwrap().header().entry_type()
+            == EntryType::GNULongName)
+        .count();
+    assert_eq!(long_names, 1);
+}
@@ -0,0 +1,46 @@
+// Archiver::content_digest
+mod common;
+
+use parallel_tar::{ArchiveOptions, Archiver};
+
+use common::Scratch;
+
+
+fn digest(scratch: & Scratch, target: & str) -> String {
+    let options = ArchiveOptions::new(& scratch.str(target));
+    Archiver::new(& scratch.str("unused"), options).content_digest().unwrap()
+}
+
+
+// Neither names nor directories change the digest
+#[test]
+fn digest_ignores_layout() {
+    let scratch = Scratch::new("digest-layout");
+    scratch.file("one/a", "first");
+    scratch.file("one/b", "second");
+    scratch.file("one/d/c", "third");
+    scratch.file("two/z/x", "third");
+    scratch.file("two/b", "first");
+    scratch.file("two/e/f/a", "second");
+    assert_eq!(digest(& scratch, "one"), digest(& scratch, "two"));
+}
+
+
+#[test]
+fn digest_sees_file_boundaries() {
+    let scratch = Scratch::new("digest-boundaries");
+    scratch.file("one/a", "ab");
+    scratch.file("one/b", "c");
+    scratch.file("two/a", "a");
+    scratch.file("two/b", "bc");
+    assert_ne!(digest(& scratch, "one"), digest(& scratch, "two"));
+}
+
+
+#[test]
+fn digest_sees_contents() {
+    let scratch = Scratch::new("digest-contents");
+    scratch.file("one/a", "a");
+    scratch.file("two/a", "b");
+    assert_ne!(digest(& scratch, "one"), digest(& scratch, "two"));
+}
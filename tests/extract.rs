@@ -0,0 +1,308 @@
+// Extracting shards through the library
+mod common;
+
+use std::fs::{File, create_dir_all, read_to_string};
+use std::path::PathBuf;
+
+use parallel_tar::{ArchiveOptions, Archiver, ArchiverError, xattrs};
+use parallel_tar::extract::{ExtractConfig, extract_shard, link_deferred};
+use parallel_tar::format::Format;
+use parallel_tar::shard::NameTemplate;
+use tar::Archive;
+
+use common::{Scratch, members};
+
+
+// Create shards of `scratch`/t as `scratch`/out/x with `threads` workers
+fn create(scratch: & Scratch, threads: u32, absolute: bool) {
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .num_threads(threads)
+        .absolute_names(absolute);
+    Archiver::new(& scratch.str("out/x"), options).create().unwrap();
+}
+
+
+// Where `scratch`/t is extracted to below `destination`: members are named
+// by their path, less the leading '/'
+fn extracted(scratch: & Scratch, destination: & str) -> PathBuf {
+    scratch.join(destination)
+        .join(scratch.join("t").strip_prefix("/").unwrap())
+}
+
+
+// Directories are archived as entries of their own, so one without files
+// comes back too
+#[test]
+fn empty_directories_round_trip() {
+    let scratch = Scratch::new("empty-directories");
+    create_dir_all(scratch.join("t/empty/nested")).unwrap();
+    scratch.file("t/full/f", "f");
+    create(& scratch, 2, false);
+
+    let options = ArchiveOptions::new(& scratch.str("dest")).num_threads(2);
+    Archiver::new(& scratch.str("out/x"), options).extract().unwrap();
+
+    let extracted = extracted(& scratch, "dest");
+    assert!(extracted.join("empty/nested").is_dir());
+    assert_eq!(read_to_string(extracted.join("full/f")).unwrap(), "f");
+}
+
+
+// Writer threads get the entry's resolved destination, so absolute member
+// names (-P) extract below the destination like they do without them
+#[test]
+fn parallel_entries_extract_absolute_names() {
+    let scratch = Scratch::new("parallel-absolute");
+    for idx in 0..20 {
+        scratch.file(& format!("t/d{}/f{}", idx % 3, idx), & idx.to_string());
+    }
+    create(& scratch, 2, true);
+
+    let options = ArchiveOptions::new(& scratch.str("dest"))
+        .num_threads(2)
+        .parallel_entries(2);
+    Archiver::new(& scratch.str("out/x"), options).extract().unwrap();
+
+    let extracted = extracted(& scratch, "dest");
+    for idx in 0..20 {
+        let file = extracted.join(format!("d{}/f{}", idx % 3, idx));
+        assert_eq!(read_to_string(& file).unwrap(), idx.to_string());
+    }
+}
+
+
+#[test]
+fn extract_creates_the_destination() {
+    let scratch = Scratch::new("missing-destination");
+    scratch.file("t/d/f", "f");
+    create(& scratch, 2, false);
+
+    let options = ArchiveOptions::new(& scratch.str("dest/new"))
+        .num_threads(2);
+    Archiver::new(& scratch.str("out/x"), options).extract().unwrap();
+
+    let extracted = extracted(& scratch, "dest/new");
+    assert_eq!(read_to_string(extracted.join("d/f")).unwrap(), "f");
+}
+
+// A hard link whose original is in another shard waits for every shard: a
+// stale file already at the original's path is not what it links to. The
+// shards are extracted one after the other, so that a link in the first one
+// would find the stale file. Originals and links are placed by path hash, which
+// with 2 shards follows the parity of the name bytes: a{N} and b{N} always
+// land in different shards.
+#[cfg(unix)]
+#[test]
+fn cross_shard_links_wait_for_their_original() {
+    use std::fs::{hard_link, metadata};
+    use std::os::unix::fs::MetadataExt;
+
+    let scratch = Scratch::new("cross-shard-links");
+    for idx in 0..40 {
+        let original = scratch.file(& format!("t/a{}", idx), "new");
+        hard_link(& original, scratch.join(& format!("t/b{}", idx))).unwrap();
+        scratch.file(& format!("dest/a{}", idx), "stale");
+        scratch.file(& format!("dest/b{}", idx), "stale");
+    }
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .num_threads(2)
+        .deterministic(true)
+        .preserve_hardlinks(true)
+        .strip_root(true);
+    Archiver::new(& scratch.str("out/x"), options).create().unwrap();
+
+    let names = NameTemplate::parse(NameTemplate::DEFAULT).unwrap();
+    let config = ExtractConfig::default();
+    let mut deferred = Vec::new();
+    for idx in 0..2 {
+        let (_, links) = extract_shard(
+            & scratch.str("out/x"), & names, idx, & scratch.str("dest"),
+            config.clone()
+        ).unwrap();
+        deferred.extend(links);
+    }
+    assert!(! deferred.is_empty());
+    link_deferred(& deferred, & config).unwrap();
+
+    for idx in 0..40 {
+        let original = scratch.join(& format!("dest/a{}", idx));
+        let link = scratch.join(& format!("dest/b{}", idx));
+        assert_eq!(read_to_string(& original).unwrap(), "new", "a{}", idx);
+        assert_eq!(read_to_string(& link).unwrap(), "new", "b{}", idx);
+        assert_eq!(
+            metadata(& original).unwrap().ino(), metadata(& link).unwrap().ino()
+        );
+    }
+}
+
+
+#[test]
+fn extract_refuses_zero_threads() {
+    let scratch = Scratch::new("extract-zero-threads");
+    scratch.file("t/a", "a");
+    create(& scratch, 1, false);
+
+    let options = ArchiveOptions::new(& scratch.str("dest")).num_threads(0);
+    let result = Archiver::new(& scratch.str("out/x"), options).extract();
+    assert!(matches!(result, Err(ArchiverError::InvalidArg(_))));
+    assert!(! scratch.join("dest").exists());
+}
+
+
+#[test]
+fn single_thread_round_trip() {
+    let scratch = Scratch::new("single-thread");
+    scratch.file("t/a", "a");
+    scratch.file("t/d/b", "b");
+    create(& scratch, 1, false);
+    assert!(! scratch.join("out/x.1.tar").exists());
+
+    let options = ArchiveOptions::new(& scratch.str("dest")).num_threads(1);
+    let report = Archiver::new(& scratch.str("out/x"), options)
+        .extract().unwrap();
+
+    let extracted = extracted(& scratch, "dest");
+    assert_eq!(read_to_string(extracted.join("a")).unwrap(), "a");
+    assert_eq!(read_to_string(extracted.join("d/b")).unwrap(), "b");
+    assert!(report.entries >= 2);
+}
+
+
+// A deduplicated file comes back as a file of its own, with its own mode and
+// mtime rather than those of the file it duplicates
+#[cfg(unix)]
+#[test]
+fn dedup_references_keep_their_metadata() {
+    use std::fs::{File, Permissions, metadata, set_permissions};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let scratch = Scratch::new("dedup-metadata");
+    let mut expected = Vec::new();
+    let files = [("a", 0o600, 1_000_000), ("b", 0o755, 2_000_000)];
+    for (name, mode, mtime) in files {
+        let path = scratch.file(& format!("t/{}", name), "same contents");
+        set_permissions(& path, Permissions::from_mode(mode)).unwrap();
+        File::options().write(true).open(& path).unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(mtime)).unwrap();
+        expected.push((name, mode, mtime as i64));
+    }
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .num_threads(1)
+        .dedup(true);
+    Archiver::new(& scratch.str("out/x"), options).create().unwrap();
+
+    let options = ArchiveOptions::new(& scratch.str("dest"))
+        .num_threads(1)
+        .same_permissions(true);
+    Archiver::new(& scratch.str("out/x"), options).extract().unwrap();
+
+    let extracted = extracted(& scratch, "dest");
+    let mut inodes = Vec::new();
+    for (name, mode, mtime) in expected {
+        let meta = metadata(extracted.join(name)).unwrap();
+        assert_eq!(meta.mode() & 0o777, mode, "{}", name);
+        assert_eq!(meta.mtime(), mtime, "{}", name);
+        inodes.push(meta.ino());
+    }
+    assert_ne!(inodes[0], inodes[1]);
+}
+
+
+// A journal from an interrupted extract skips the entries it lists (by their
+// raw header path, whether UTF-8 or not) if they are still there, and is
+// removed once the extract completes
+#[cfg(unix)]
+#[test]
+fn journal_resumes_an_interrupted_extract() {
+    use std::ffi::OsStr;
+    use std::fs::write;
+    use std::os::unix::ffi::OsStrExt;
+    use parallel_tar::journal::Journal;
+
+    let scratch = Scratch::new("extract-journal");
+    let odd = OsStr::from_bytes(b"odd-\xff");
+    create_dir_all(scratch.join("t")).unwrap();
+    for name in [OsStr::new("a"), OsStr::new("b"), odd] {
+        write(scratch.join("t").join(name), "archived").unwrap();
+    }
+    create(& scratch, 1, false);
+
+    // The interrupted run wrote "a" and the odd name
+    let extracted = extracted(& scratch, "dest");
+    create_dir_all(& extracted).unwrap();
+    let journal_path = scratch.str("journal");
+    let mut journal = Journal::open(& journal_path).unwrap();
+    for name in [OsStr::new("a"), odd] {
+        write(extracted.join(name), "first run").unwrap();
+        let member = scratch.join("t").join(name);
+        let member = member.strip_prefix("/").unwrap().as_os_str();
+        journal.record(0, member.as_bytes()).unwrap();
+    }
+    drop(journal);
+
+    let options = ArchiveOptions::new(& scratch.str("dest"))
+        .num_threads(1)
+        .journal(Some(& journal_path));
+    let report = Archiver::new(& scratch.str("out/x"), options)
+        .extract().unwrap();
+
+    assert_eq!(report.skipped, 2);
+    let read = |name: & OsStr| read_to_string(extracted.join(name)).unwrap();
+    assert_eq!(read(OsStr::new("a")), "first run");
+    assert_eq!(read(odd), "first run");
+    assert_eq!(read(OsStr::new("b")), "archived");
+    assert!(! scratch.join("journal").exists());
+}
+
+
+// Only the index create --shard-index writes is left out, not a file of the
+// tree that has its name. The index is marked by a standard PAX comment, so
+// ustar (which has no PAX headers) is refused.
+#[test]
+fn shard_index_name_in_the_tree_is_extracted() {
+    let scratch = Scratch::new("shard-index-name");
+    scratch.file("t/.ptar-shard-index.json", "mine");
+    scratch.file("t/a", "a");
+    for format in [Format::Gnu, Format::Pax] {
+        let out = scratch.str(& format!("{}/x", format));
+        let options = ArchiveOptions::new(& scratch.str("t"))
+            .num_threads(1)
+            .strip_root(true)
+            .format(format)
+            .shard_index(true);
+        Archiver::new(& out, options).create().unwrap();
+        let tar_path = PathBuf::from(format!("{}.0.tar", out));
+        let archived = members(& tar_path);
+        assert_eq!(
+            archived.iter().filter(|m| * m == ".ptar-shard-index.json")
+                .count(),
+            2
+        );
+        let mut ar = Archive::new(File::open(& tar_path).unwrap());
+        let mut entries = ar.entries().unwrap();
+        let mut index = entries.nth(archived.len() - 1).unwrap().unwrap();
+        let records = xattrs::entry_records(& mut index).unwrap();
+        assert_eq!(
+            records, [(b"comment".to_vec(), b"ptar-shard-index".to_vec())]
+        );
+
+        let dest = scratch.str(& format!("{}/dest", format));
+        let options = ArchiveOptions::new(& dest).num_threads(1);
+        let report = Archiver::new(& out, options).extract().unwrap();
+
+        let index = PathBuf::from(& dest).join(".ptar-shard-index.json");
+        assert_eq!(read_to_string(index).unwrap(), "mine");
+        let a = PathBuf::from(& dest).join("a");
+        assert_eq!(read_to_string(a).unwrap(), "a");
+        assert_eq!(report.entries, 2);
+    }
+
+    let options = ArchiveOptions::new(& scratch.str("t"))
+        .num_threads(1)
+        .format(Format::Ustar)
+        .shard_index(true);
+    let result = Archiver::new(& scratch.str("ustar/x"), options).create();
+    assert!(matches!(result, Err(ArchiverError::InvalidArg(_))));
+    assert!(! scratch.join("ustar").exists());
+}